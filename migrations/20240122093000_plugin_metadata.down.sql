@@ -0,0 +1,10 @@
+-- Add down migration script here
+ALTER TABLE plugins
+    DROP COLUMN name,
+    DROP COLUMN version,
+    DROP COLUMN author,
+    DROP COLUMN license,
+    DROP COLUMN homepage_url,
+    DROP COLUMN repository_url,
+    DROP COLUMN created_at,
+    DROP COLUMN updated_at;
@@ -0,0 +1,17 @@
+-- Add up migration script here
+ALTER TABLE plugins
+    ADD COLUMN name TEXT,
+    ADD COLUMN version TEXT NOT NULL DEFAULT '0.1.0',
+    ADD COLUMN author TEXT,
+    ADD COLUMN license TEXT,
+    ADD COLUMN homepage_url TEXT,
+    ADD COLUMN repository_url TEXT,
+    ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+-- Existing plugins never had a name, give them a stable placeholder slug.
+UPDATE plugins SET name = 'plugin-' || id WHERE name IS NULL;
+
+ALTER TABLE plugins
+    ALTER COLUMN name SET NOT NULL,
+    ADD CONSTRAINT plugins_name_key UNIQUE (name);
@@ -0,0 +1,13 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS plugin_versions (
+    id SERIAL PRIMARY KEY,
+    plugin_id INTEGER NOT NULL REFERENCES plugins (id) ON DELETE CASCADE,
+    version TEXT NOT NULL,
+    wasm_url TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (plugin_id, version)
+);
+
+-- Every existing plugin becomes the first release of itself.
+INSERT INTO plugin_versions (plugin_id, version, wasm_url, created_at)
+SELECT id, version, wasm_url, created_at FROM plugins;
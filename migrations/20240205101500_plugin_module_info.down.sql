@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE plugin_versions DROP COLUMN module_info;
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE plugin_versions ADD COLUMN module_info JSONB;
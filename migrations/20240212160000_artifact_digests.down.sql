@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP INDEX plugin_versions_sha256_idx;
+
+ALTER TABLE plugins DROP COLUMN sha256;
+ALTER TABLE plugin_versions DROP COLUMN sha256;
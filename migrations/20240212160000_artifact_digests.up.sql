@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE plugin_versions ADD COLUMN sha256 TEXT;
+ALTER TABLE plugins ADD COLUMN sha256 TEXT;
+
+CREATE INDEX plugin_versions_sha256_idx ON plugin_versions (sha256);
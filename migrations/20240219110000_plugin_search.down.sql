@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX plugins_search_idx;
+
+ALTER TABLE plugins DROP COLUMN search;
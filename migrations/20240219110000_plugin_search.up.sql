@@ -0,0 +1,7 @@
+-- Add up migration script here
+ALTER TABLE plugins ADD COLUMN search tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', name), 'A') ||
+    setweight(to_tsvector('english', description), 'B')
+) STORED;
+
+CREATE INDEX plugins_search_idx ON plugins USING GIN (search);
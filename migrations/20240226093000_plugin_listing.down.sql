@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP INDEX plugins_downloads_idx;
+DROP INDEX plugins_newest_idx;
+
+ALTER TABLE plugins DROP COLUMN downloads;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE plugins ADD COLUMN downloads BIGINT NOT NULL DEFAULT 0;
+
+CREATE INDEX plugins_newest_idx ON plugins (created_at DESC, id DESC);
+CREATE INDEX plugins_downloads_idx ON plugins (downloads DESC, id DESC);
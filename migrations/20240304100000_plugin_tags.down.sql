@@ -0,0 +1,20 @@
+-- Add down migration script here
+DROP TRIGGER plugin_tags_search ON plugin_tags;
+DROP FUNCTION plugin_tags_search_trigger();
+DROP TRIGGER plugins_search ON plugins;
+DROP FUNCTION plugins_search_trigger();
+DROP FUNCTION plugin_search_document(INT, TEXT, TEXT);
+
+DROP INDEX plugins_search_idx;
+ALTER TABLE plugins DROP COLUMN search;
+ALTER TABLE plugins ADD COLUMN search tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', name), 'A') ||
+    setweight(to_tsvector('english', description), 'B')
+) STORED;
+CREATE INDEX plugins_search_idx ON plugins USING GIN (search);
+
+DROP TABLE plugin_tags;
+DROP TABLE tags;
+
+DROP INDEX plugins_category_idx;
+ALTER TABLE plugins DROP COLUMN category;
@@ -0,0 +1,58 @@
+-- Add up migration script here
+ALTER TABLE plugins ADD COLUMN category TEXT;
+CREATE INDEX plugins_category_idx ON plugins (category);
+
+CREATE TABLE tags (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE plugin_tags (
+    plugin_id INT NOT NULL REFERENCES plugins (id) ON DELETE CASCADE,
+    tag_id INT NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+    PRIMARY KEY (plugin_id, tag_id)
+);
+CREATE INDEX plugin_tags_tag_id_idx ON plugin_tags (tag_id);
+
+-- Tags live in their own table, so the search document can no longer be a
+-- generated column. Triggers keep it up to date instead.
+DROP INDEX plugins_search_idx;
+ALTER TABLE plugins DROP COLUMN search;
+ALTER TABLE plugins ADD COLUMN search tsvector;
+
+CREATE FUNCTION plugin_search_document(plugin_id INT, name TEXT, description TEXT)
+RETURNS tsvector LANGUAGE sql STABLE AS $$
+    SELECT setweight(to_tsvector('english', name), 'A') ||
+        setweight(to_tsvector('english', coalesce((
+            SELECT string_agg(tags.name, ' ')
+            FROM plugin_tags JOIN tags ON tags.id = plugin_tags.tag_id
+            WHERE plugin_tags.plugin_id = plugin_search_document.plugin_id
+        ), '')), 'A') ||
+        setweight(to_tsvector('english', description), 'B')
+$$;
+
+CREATE FUNCTION plugins_search_trigger() RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+    NEW.search := plugin_search_document(NEW.id, NEW.name, NEW.description);
+    RETURN NEW;
+END
+$$;
+
+CREATE TRIGGER plugins_search BEFORE INSERT OR UPDATE OF name, description ON plugins
+FOR EACH ROW EXECUTE FUNCTION plugins_search_trigger();
+
+CREATE FUNCTION plugin_tags_search_trigger() RETURNS trigger LANGUAGE plpgsql AS $$
+DECLARE
+    changed_plugin_id INT := CASE WHEN TG_OP = 'DELETE' THEN OLD.plugin_id ELSE NEW.plugin_id END;
+BEGIN
+    UPDATE plugins SET search = plugin_search_document(id, name, description)
+    WHERE id = changed_plugin_id;
+    RETURN NULL;
+END
+$$;
+
+CREATE TRIGGER plugin_tags_search AFTER INSERT OR DELETE ON plugin_tags
+FOR EACH ROW EXECUTE FUNCTION plugin_tags_search_trigger();
+
+UPDATE plugins SET search = plugin_search_document(id, name, description);
+CREATE INDEX plugins_search_idx ON plugins USING GIN (search);
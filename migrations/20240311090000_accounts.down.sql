@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE plugins DROP COLUMN owner_id;
+DROP TABLE sessions;
+DROP TABLE users;
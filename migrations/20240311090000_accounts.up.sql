@@ -0,0 +1,23 @@
+-- Add up migration script here
+CREATE TABLE users (
+    id SERIAL PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Only a digest of each session token is kept, so a leaked table can't be
+-- used to log in.
+CREATE TABLE sessions (
+    token_hash TEXT PRIMARY KEY,
+    user_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
+
+-- Plugins published before accounts existed have no owner and can only be
+-- changed by admins.
+ALTER TABLE plugins ADD COLUMN owner_id INT REFERENCES users (id) ON DELETE SET NULL;
+CREATE INDEX plugins_owner_id_idx ON plugins (owner_id);
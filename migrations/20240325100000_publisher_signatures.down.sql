@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE plugin_versions DROP COLUMN signature;
+ALTER TABLE plugins DROP COLUMN npub;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- The publisher's Nostr public key, bech32 encoded, and their Schnorr
+-- signature of each release's manifest (name, version and artifact digest).
+ALTER TABLE plugins ADD COLUMN npub TEXT;
+ALTER TABLE plugin_versions ADD COLUMN signature TEXT;
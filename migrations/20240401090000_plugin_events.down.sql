@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE plugin_events;
+DROP TYPE plugin_mutation;
@@ -0,0 +1,12 @@
+-- Add up migration script here
+CREATE TYPE plugin_mutation AS ENUM ('create', 'update', 'delete');
+
+-- Every change to a plugin, numbered in commit order so clients that
+-- reconnect to the event stream can catch up on what they missed. Deleted
+-- plugins keep their history.
+CREATE TABLE plugin_events (
+    id BIGSERIAL PRIMARY KEY,
+    plugin_id INT NOT NULL,
+    mutation_kind plugin_mutation NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,34 @@
+-- Add up migration script here
+CREATE TABLE webhooks (
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    -- Key deliveries are signed with. Kept as is, as signing needs it.
+    secret TEXT NOT NULL,
+    -- Kinds of change to deliver, and optionally which plugins.
+    events TEXT[] NOT NULL,
+    plugin_id INT,
+    tag TEXT,
+    owner TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX webhooks_user_id_idx ON webhooks (user_id);
+
+-- Every event sent to a webhook, which doubles as the retry queue.
+CREATE TABLE webhook_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    webhook_id INT NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    -- When to try next; NULL once delivered or given up on.
+    next_attempt_at TIMESTAMPTZ DEFAULT now(),
+    delivered_at TIMESTAMPTZ,
+    -- Outcome of the last attempt.
+    response_status INT,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX webhook_deliveries_webhook_id_idx ON webhook_deliveries (webhook_id, id DESC);
+CREATE INDEX webhook_deliveries_due_idx ON webhook_deliveries (next_attempt_at)
+    WHERE next_attempt_at IS NOT NULL;
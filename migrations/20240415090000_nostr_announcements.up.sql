@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- The latest event announcing each plugin, so the deletion request sent
+-- when it is deleted can name that event as well as its address. Kept
+-- without a foreign key: the row is read after the plugin is gone.
+CREATE TABLE nostr_announcements (
+    plugin_id INT PRIMARY KEY,
+    event_id TEXT NOT NULL
+);
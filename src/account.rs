@@ -0,0 +1,351 @@
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, SaltString};
+use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, State},
+    http::{header, request::Parts, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgExecutor;
+
+use crate::config::Config;
+use crate::error::{JsonError, RegistryError};
+use crate::plugin::Plugin;
+use crate::token::{ApiToken, Scope, TOKEN_PREFIX};
+use crate::AppState;
+
+/// Cookie the HTML pages keep the session token in. API clients send the
+/// same token, or a personal API token, as `Authorization: Bearer <token>`
+/// instead.
+pub const SESSION_COOKIE: &str = "registry_session";
+
+#[derive(Clone, sqlx::FromRow, Serialize)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    #[serde(skip)]
+    pub password_hash: String,
+    /// Admins can change any plugin. There is no UI for this; promote users
+    /// with `UPDATE users SET is_admin = TRUE`.
+    pub is_admin: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What a user signs up and logs in with.
+#[derive(Default, Deserialize)]
+pub struct Credentials {
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+}
+
+impl User {
+    pub async fn register(
+        db: impl PgExecutor<'_>,
+        credentials: &Credentials,
+    ) -> Result<User, RegistryError> {
+        let password_hash = hash_password(credentials.password.clone()).await;
+
+        sqlx::query_as::<_, User>(
+            "INSERT INTO users (username, password_hash) VALUES ($1, $2) RETURNING *",
+        )
+        .bind(&credentials.username)
+        .bind(password_hash)
+        .fetch_one(db)
+        .await
+        .map_err(|err| match RegistryError::from(err) {
+            RegistryError::Conflict(_) => RegistryError::validation("username", "is already taken"),
+            err => err,
+        })
+    }
+
+    /// The user with these credentials. Unknown users and wrong passwords
+    /// get the same error so usernames can't be probed.
+    pub async fn authenticate(
+        db: impl PgExecutor<'_>,
+        credentials: &Credentials,
+    ) -> Result<User, RegistryError> {
+        let invalid = || RegistryError::Unauthorized("invalid username or password".to_owned());
+
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
+            .bind(&credentials.username)
+            .fetch_optional(db)
+            .await?
+            .ok_or_else(invalid)?;
+
+        if verify_password(credentials.password.clone(), user.password_hash.clone()).await {
+            Ok(user)
+        } else {
+            Err(invalid())
+        }
+    }
+
+    pub fn can_modify(&self, plugin: &Plugin) -> bool {
+        self.is_admin || plugin.owner_id == Some(self.id)
+    }
+
+    /// Loads plugin `id` for a change only its owner or an admin may make.
+    pub async fn plugin_to_modify(
+        &self,
+        db: impl PgExecutor<'_>,
+        id: i32,
+    ) -> Result<Plugin, RegistryError> {
+        let plugin = Plugin::find(db, id).await?;
+        if !self.can_modify(&plugin) {
+            return Err(RegistryError::Forbidden(format!(
+                "only the owner of {} can change it",
+                plugin.name
+            )));
+        }
+
+        Ok(plugin)
+    }
+}
+
+/// A login. Tokens are random and only their SHA-256 digest is stored.
+pub struct Session {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Session {
+    pub async fn start(
+        db: impl PgExecutor<'_>,
+        config: &Config,
+        user_id: i32,
+    ) -> Result<Session, RegistryError> {
+        let token = hex::encode(rand::random::<[u8; 32]>());
+        let expires_at = Utc::now() + Duration::hours(config.session_ttl_hours);
+
+        sqlx::query("INSERT INTO sessions (token_hash, user_id, expires_at) VALUES ($1, $2, $3)")
+            .bind(token_hash(&token))
+            .bind(user_id)
+            .bind(expires_at)
+            .execute(db)
+            .await?;
+
+        Ok(Session { token, expires_at })
+    }
+
+    pub async fn end(db: impl PgExecutor<'_>, token: &str) -> Result<(), RegistryError> {
+        sqlx::query("DELETE FROM sessions WHERE token_hash = $1")
+            .bind(token_hash(token))
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn user(db: impl PgExecutor<'_>, token: &str) -> Result<Option<User>, RegistryError> {
+        let user = sqlx::query_as::<_, User>(
+            "SELECT users.* FROM sessions JOIN users ON users.id = sessions.user_id
+             WHERE sessions.token_hash = $1 AND sessions.expires_at > now()",
+        )
+        .bind(token_hash(token))
+        .fetch_optional(db)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// `Set-Cookie` value that stores this session in the browser.
+    pub fn cookie(&self, config: &Config) -> String {
+        let max_age = (self.expires_at - Utc::now()).num_seconds().max(0);
+        session_cookie(config, &self.token, max_age)
+    }
+
+    /// `Set-Cookie` value that logs the browser out.
+    pub fn removal_cookie(config: &Config) -> String {
+        session_cookie(config, "", 0)
+    }
+}
+
+fn session_cookie(config: &Config, token: &str, max_age: i64) -> String {
+    let secure = if config.secure_cookies() {
+        "; Secure"
+    } else {
+        ""
+    };
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{}",
+        SESSION_COOKIE, token, max_age, secure
+    )
+}
+
+pub fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Session or API token from the `Authorization` header, falling back to
+/// the session cookie.
+pub fn session_token(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(token) = bearer {
+        return Some(token.trim());
+    }
+
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, token)| token)
+        .filter(|token| !token.is_empty())
+}
+
+// Hashing is deliberately slow, so keep it off the async workers.
+async fn hash_password(password: String) -> String {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("Looks like hashing a password failed :(")
+            .to_string()
+    })
+    .await
+    .expect("Looks like hashing a password panicked :(")
+}
+
+async fn verify_password(password: String, password_hash: String) -> bool {
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&password_hash)
+            .map(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    })
+    .await
+    .expect("Looks like verifying a password panicked :(")
+}
+
+/// The logged in user, if any. Invalid or expired credentials count as
+/// being logged out.
+pub struct CurrentUser(pub Option<User>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for CurrentUser {
+    type Rejection = RegistryError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let auth = Authenticated::from_request_parts(parts, state).await;
+
+        match auth {
+            Ok(auth) => Ok(Self(Some(auth.user))),
+            Err(RegistryError::Unauthorized(_)) => Ok(Self(None)),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// The user a request is made on behalf of, rejecting the request when there
+/// is none. Handlers say what they need it for with [`Authenticated::scoped`].
+#[derive(Clone)]
+pub struct Authenticated {
+    user: User,
+    /// Scopes of the API token the request was made with, `None` for
+    /// browser and API sessions, which can do everything.
+    scopes: Option<Vec<Scope>>,
+}
+
+impl Authenticated {
+    /// The user, provided the credential allows `scope`.
+    pub fn scoped(self, scope: Scope) -> Result<User, RegistryError> {
+        match &self.scopes {
+            Some(scopes) if !scopes.contains(&scope) => Err(RegistryError::Forbidden(format!(
+                "this token lacks the {} scope",
+                scope
+            ))),
+            _ => Ok(self.user),
+        }
+    }
+
+    /// The user, provided they logged in with their password rather than an
+    /// API token. Tokens can't be used to manage tokens.
+    pub fn session(self) -> Result<User, RegistryError> {
+        match self.scopes {
+            Some(_) => Err(RegistryError::Forbidden(
+                "API tokens can't do this, log in instead".to_owned(),
+            )),
+            None => Ok(self.user),
+        }
+    }
+
+    async fn from_headers(state: &AppState, headers: &HeaderMap) -> Result<Self, RegistryError> {
+        let token = session_token(headers)
+            .ok_or_else(|| RegistryError::Unauthorized("log in to do this".to_owned()))?;
+
+        let auth = if token.starts_with(TOKEN_PREFIX) {
+            ApiToken::authenticate(&state.db, token)
+                .await?
+                .map(|(user, scopes)| Self {
+                    user,
+                    scopes: Some(scopes),
+                })
+        } else {
+            Session::user(&state.db, token)
+                .await?
+                .map(|user| Self { user, scopes: None })
+        };
+
+        auth.ok_or_else(|| {
+            RegistryError::Unauthorized("your login or token is invalid or has expired".to_owned())
+        })
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Authenticated {
+    type Rejection = RegistryError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        // Already checked by `require_auth` on the mutating routes.
+        if let Some(auth) = parts.extensions.get::<Authenticated>() {
+            return Ok(auth.clone());
+        }
+
+        Self::from_headers(state, &parts.headers).await
+    }
+}
+
+/// Middleware for routes that change things: anything but `GET`, `HEAD` and
+/// `OPTIONS` needs a valid session or API token. Handlers still check the
+/// scope and ownership they need.
+pub async fn require_auth<B>(
+    State(state): State<AppState>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, RegistryError> {
+    if !req.method().is_safe() {
+        let auth = Authenticated::from_headers(&state, req.headers()).await?;
+        req.extensions_mut().insert(auth);
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// [`require_auth`] for the JSON API.
+pub async fn require_api_auth<B>(
+    state: State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, JsonError> {
+    Ok(require_auth(state, req, next).await?)
+}
@@ -0,0 +1,535 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures_util::{future::join_all, SinkExt, StreamExt};
+use secp256k1::Keypair;
+use serde_json::json;
+use sqlx::{Connection, PgConnection, PgPool};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use crate::config::Config;
+use crate::error::RegistryError;
+use crate::metrics::StreamMetrics;
+use crate::nostr::{self, Event};
+use crate::plugin::Plugin;
+use crate::{MutationKind, PluginUpdate, PluginsStream};
+
+/// NIP-89 handler information, a parameterized replaceable event: each
+/// plugin is announced under its id, and every change replaces the previous
+/// announcement.
+pub const HANDLER_INFORMATION: u32 = 31990;
+/// NIP-09 deletion request, retracting a deleted plugin's announcement.
+pub const DELETION: u32 = 5;
+
+/// Postgres advisory lock held by the one replica that announces changes.
+const ANNOUNCER_LOCK: i64 = 0x6e6f737472;
+
+/// How long a relay gets to acknowledge an event.
+const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub struct RelayError(pub String);
+
+impl fmt::Display for RelayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Sends events to a relay. [`WebSocketRelay`] talks to real relays; other
+/// implementations can stand in for them.
+#[async_trait]
+pub trait RelayClient: Send + Sync {
+    async fn publish(&self, relay: &str, event: &Event) -> Result<(), RelayError>;
+}
+
+/// Publishes over a fresh WebSocket per event, as NIP-01 `["EVENT", ...]`
+/// messages, and waits for the relay's `["OK", ...]`.
+pub struct WebSocketRelay;
+
+#[async_trait]
+impl RelayClient for WebSocketRelay {
+    async fn publish(&self, relay: &str, event: &Event) -> Result<(), RelayError> {
+        tokio::time::timeout(RELAY_TIMEOUT, send_event(relay, event))
+            .await
+            .map_err(|_| RelayError("timed out waiting for the relay".to_owned()))?
+    }
+}
+
+async fn send_event(relay: &str, event: &Event) -> Result<(), RelayError> {
+    let error = |err: tokio_tungstenite::tungstenite::Error| RelayError(err.to_string());
+
+    let (mut socket, _) = connect_async(relay).await.map_err(error)?;
+    socket
+        .send(Message::Text(json!(["EVENT", event]).to_string()))
+        .await
+        .map_err(error)?;
+
+    // Relays may send notices and other messages before the acknowledgement.
+    while let Some(message) = socket.next().await {
+        let Message::Text(text) = message.map_err(error)? else {
+            continue;
+        };
+        let Ok(reply) = serde_json::from_str::<serde_json::Value>(&text) else {
+            continue;
+        };
+
+        if reply[0] == "OK" && reply[1] == event.id.as_str() {
+            let _ = socket.close(None).await;
+            return match reply[2].as_bool() {
+                Some(true) => Ok(()),
+                _ => Err(RelayError(format!(
+                    "relay rejected the event: {}",
+                    reply[3].as_str().unwrap_or_default()
+                ))),
+            };
+        }
+    }
+
+    Err(RelayError(
+        "relay closed the connection without acknowledging the event".to_owned(),
+    ))
+}
+
+/// Announces plugin changes as Nostr events signed by the registry.
+#[derive(Clone)]
+pub struct Announcer {
+    keys: Keypair,
+    relays: Vec<String>,
+    client: Arc<dyn RelayClient>,
+}
+
+impl Announcer {
+    pub fn new(keys: Keypair, relays: Vec<String>, client: Arc<dyn RelayClient>) -> Self {
+        Self {
+            keys,
+            relays,
+            client,
+        }
+    }
+
+    /// An announcer publishing to real relays, if the registry has a key and
+    /// relays configured.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.nostr_relays.is_empty() {
+            return None;
+        }
+        let Some(keys) = config
+            .nostr_secret_key
+            .as_deref()
+            .and_then(nostr::parse_secret_key)
+        else {
+            eprintln!("Not announcing plugin changes: NOSTR_SECRET_KEY is missing or invalid");
+            return None;
+        };
+
+        Some(Self::new(
+            keys,
+            config.nostr_relays.clone(),
+            Arc::new(WebSocketRelay),
+        ))
+    }
+
+    /// The registry's public key, which announcements are signed with.
+    pub fn npub(&self) -> String {
+        nostr::npub(&self.keys.x_only_public_key().0)
+    }
+
+    /// Announces every change sent on `tx` until the registry shuts down.
+    /// Every replica hears about every change, so only the one holding the
+    /// announcer lock publishes them; another takes over if it goes away.
+    pub fn spawn(self, db: PgPool, tx: &PluginsStream, metrics: Arc<StreamMetrics>) {
+        let mut rx = tx.subscribe();
+
+        tokio::spawn(async move {
+            let mut lock = AnnouncerLock::default();
+            loop {
+                match rx.recv().await {
+                    Ok(update) => match lock.held(&db).await {
+                        Ok(true) => self.announce(&db, &update).await,
+                        Ok(false) => {}
+                        Err(err) => eprintln!(
+                            "Couldn't announce plugin {}, taking the announcer lock failed: {}",
+                            update.id, err
+                        ),
+                    },
+                    Err(RecvError::Lagged(missed)) => {
+                        metrics.record_lag(missed);
+                        eprintln!("Missed announcing {} plugin changes", missed)
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Signs an event for `update` and publishes it to every relay. Failures
+    /// are logged; the change itself has already happened.
+    pub async fn announce(&self, db: &PgPool, update: &PluginUpdate) {
+        let result = match update.mutation_kind {
+            MutationKind::Delete => self.announce_deletion(db, update.id).await,
+            MutationKind::Create | MutationKind::Update => {
+                match Plugin::find(db, update.id).await {
+                    Ok(plugin) => self.announce_plugin(db, &plugin).await,
+                    // Deleted again before it could be announced.
+                    Err(RegistryError::NotFound(_)) => Ok(()),
+                    Err(err) => Err(err),
+                }
+            }
+        };
+        if let Err(err) = result {
+            eprintln!("Couldn't announce plugin {}: {:?}", update.id, err);
+        }
+    }
+
+    async fn announce_plugin(&self, db: &PgPool, plugin: &Plugin) -> Result<(), RegistryError> {
+        let event = self.handler_information(plugin);
+        self.publish(plugin.id, &event).await;
+
+        sqlx::query(
+            "INSERT INTO nostr_announcements (plugin_id, event_id) VALUES ($1, $2)
+             ON CONFLICT (plugin_id) DO UPDATE SET event_id = EXCLUDED.event_id",
+        )
+        .bind(plugin.id)
+        .bind(&event.id)
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn announce_deletion(&self, db: &PgPool, id: i32) -> Result<(), RegistryError> {
+        let announced: Option<String> = sqlx::query_scalar(
+            "DELETE FROM nostr_announcements WHERE plugin_id = $1 RETURNING event_id",
+        )
+        .bind(id)
+        .fetch_optional(db)
+        .await?;
+
+        self.publish(id, &self.deletion(id, announced.as_deref()))
+            .await;
+        Ok(())
+    }
+
+    /// Sends `event`, about plugin `id`, to every relay.
+    async fn publish(&self, id: i32, event: &Event) {
+        let results = join_all(
+            self.relays
+                .iter()
+                .map(|relay| self.client.publish(relay, event)),
+        )
+        .await;
+        for (relay, result) in self.relays.iter().zip(results) {
+            if let Err(err) = result {
+                eprintln!("Couldn't announce plugin {} to {}: {}", id, relay, err);
+            }
+        }
+    }
+
+    /// The plugin as NIP-89 handler information. The content is kind 0 style
+    /// metadata; the tags reference the current version's artifact by its
+    /// SHA-256 (`x`, as in NIP-94), where to download it and its publisher.
+    pub fn handler_information(&self, plugin: &Plugin) -> Event {
+        let mut tags = vec![
+            tag(["d", &plugin.id.to_string()]),
+            tag(["name", &plugin.name]),
+            tag(["version", &plugin.version]),
+            tag(["url", &plugin.wasm_url]),
+            tag([
+                "alt",
+                &format!("WASM plugin {} {}", plugin.name, plugin.version),
+            ]),
+        ];
+        if let Some(sha256) = &plugin.sha256 {
+            tags.push(tag(["x", sha256]));
+        }
+        if let Some(publisher) = plugin.npub.as_deref().and_then(nostr::parse_public_key) {
+            tags.push(tag(["p", &hex::encode(publisher.serialize())]));
+        }
+        tags.extend(plugin.tags.iter().map(|name| tag(["t", name])));
+
+        let mut content = json!({
+            "name": plugin.name,
+            "about": plugin.description,
+        });
+        if let Some(homepage_url) = &plugin.homepage_url {
+            content["website"] = json!(homepage_url);
+        }
+
+        Event::sign(&self.keys, HANDLER_INFORMATION, tags, content.to_string())
+    }
+
+    /// Asks relays to drop a deleted plugin's announcement: the latest
+    /// announced event by its id (`e`), when known, and every version of it
+    /// by its address (`a`).
+    pub fn deletion(&self, id: i32, announced: Option<&str>) -> Event {
+        let address = format!(
+            "{}:{}:{}",
+            HANDLER_INFORMATION,
+            hex::encode(self.keys.x_only_public_key().0.serialize()),
+            id
+        );
+
+        let mut tags = Vec::new();
+        if let Some(event_id) = announced {
+            tags.push(tag(["e", event_id]));
+        }
+        tags.push(tag(["a", &address]));
+        tags.push(tag(["k", &HANDLER_INFORMATION.to_string()]));
+
+        Event::sign(
+            &self.keys,
+            DELETION,
+            tags,
+            "plugin deleted from the registry".to_owned(),
+        )
+    }
+}
+
+/// The announcer lock, taken on a connection of its own that is kept open
+/// for as long as the lock is held. Postgres releases the lock when that
+/// connection goes away.
+#[derive(Default)]
+struct AnnouncerLock {
+    conn: Option<PgConnection>,
+    held: bool,
+}
+
+impl AnnouncerLock {
+    /// Whether this replica holds the lock, trying to take it if not.
+    async fn held(&mut self, db: &PgPool) -> Result<bool, sqlx::Error> {
+        if let Some(conn) = &mut self.conn {
+            if conn.ping().await.is_err() {
+                // The lock went with the connection.
+                self.conn = None;
+                self.held = false;
+            }
+        }
+
+        let conn = match &mut self.conn {
+            Some(conn) => conn,
+            None => self
+                .conn
+                .insert(PgConnection::connect_with(&db.connect_options()).await?),
+        };
+        // Advisory locks count how often they were taken, so only ask while
+        // not holding it.
+        if !self.held {
+            self.held = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+                .bind(ANNOUNCER_LOCK)
+                .fetch_one(conn)
+                .await?;
+        }
+
+        Ok(self.held)
+    }
+}
+
+fn tag<const N: usize>(values: [&str; N]) -> Vec<String> {
+    values.into_iter().map(str::to_owned).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::sync::Mutex;
+
+    /// Keeps every event it is sent instead of talking to a relay.
+    #[derive(Default)]
+    struct RecordingRelay {
+        sent: Mutex<Vec<(String, Event)>>,
+    }
+
+    #[async_trait]
+    impl RelayClient for RecordingRelay {
+        async fn publish(&self, relay: &str, event: &Event) -> Result<(), RelayError> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push((relay.to_owned(), event.clone()));
+            Ok(())
+        }
+    }
+
+    const RELAYS: [&str; 2] = ["wss://one.example", "wss://two.example"];
+
+    fn announcer() -> (Announcer, Arc<RecordingRelay>) {
+        let relay = Arc::new(RecordingRelay::default());
+        let keys = nostr::parse_secret_key(&"01".repeat(32)).unwrap();
+        let relays = RELAYS.map(str::to_owned).to_vec();
+        (Announcer::new(keys, relays, relay.clone()), relay)
+    }
+
+    fn plugin() -> Plugin {
+        Plugin {
+            id: 42,
+            name: "summarize-text".to_owned(),
+            version: "1.0.0".to_owned(),
+            description: "Summarizes text".to_owned(),
+            wasm_url: "https://example.com/summarize.wasm".to_owned(),
+            sha256: Some("ab".repeat(32)),
+            author: None,
+            license: None,
+            homepage_url: Some("https://example.com".to_owned()),
+            repository_url: None,
+            category: None,
+            tags: vec!["ai".to_owned(), "text".to_owned()],
+            owner_id: None,
+            owner: None,
+            npub: Some(
+                "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg".to_owned(),
+            ),
+            downloads: 0,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// Publishes `event` and returns what each relay was sent.
+    async fn published(announcer: &Announcer, relay: &RecordingRelay, event: &Event) -> Event {
+        announcer.publish(42, event).await;
+        let sent = std::mem::take(&mut *relay.sent.lock().unwrap());
+
+        assert_eq!(
+            sent.iter()
+                .map(|(relay, _)| relay.as_str())
+                .collect::<Vec<_>>(),
+            RELAYS
+        );
+        for (_, sent) in &sent {
+            assert_eq!(sent.id, event.id);
+            assert!(sent.is_valid());
+        }
+        sent[0].1.clone()
+    }
+
+    fn tags(event: &Event) -> Vec<Vec<&str>> {
+        event
+            .tags
+            .iter()
+            .map(|tag| tag.iter().map(String::as_str).collect())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn created_plugins_are_announced_as_handler_information() {
+        let (announcer, relay) = announcer();
+        let event = published(
+            &announcer,
+            &relay,
+            &announcer.handler_information(&plugin()),
+        )
+        .await;
+
+        assert_eq!(event.kind, HANDLER_INFORMATION);
+        assert_eq!(
+            event.pubkey,
+            hex::encode(announcer.keys.x_only_public_key().0.serialize())
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&event.content).unwrap(),
+            json!({
+                "name": "summarize-text",
+                "about": "Summarizes text",
+                "website": "https://example.com",
+            })
+        );
+        let sha256 = "ab".repeat(32);
+        assert_eq!(
+            tags(&event),
+            [
+                vec!["d", "42"],
+                vec!["name", "summarize-text"],
+                vec!["version", "1.0.0"],
+                vec!["url", "https://example.com/summarize.wasm"],
+                vec!["alt", "WASM plugin summarize-text 1.0.0"],
+                vec!["x", &sha256],
+                vec![
+                    "p",
+                    "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e"
+                ],
+                vec!["t", "ai"],
+                vec!["t", "text"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn updates_replace_the_announcement() {
+        let (announcer, relay) = announcer();
+        let created = published(
+            &announcer,
+            &relay,
+            &announcer.handler_information(&plugin()),
+        )
+        .await;
+
+        let updated = Plugin {
+            version: "1.1.0".to_owned(),
+            sha256: None,
+            homepage_url: None,
+            npub: None,
+            tags: Vec::new(),
+            ..plugin()
+        };
+        let event = published(&announcer, &relay, &announcer.handler_information(&updated)).await;
+
+        // Same kind, author and `d` tag, so relays keep only the newer one.
+        assert_ne!(event.id, created.id);
+        assert_eq!(event.kind, created.kind);
+        assert_eq!(event.pubkey, created.pubkey);
+        assert_eq!(
+            tags(&event),
+            [
+                vec!["d", "42"],
+                vec!["name", "summarize-text"],
+                vec!["version", "1.1.0"],
+                vec!["url", "https://example.com/summarize.wasm"],
+                vec!["alt", "WASM plugin summarize-text 1.1.0"],
+            ]
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&event.content).unwrap(),
+            json!({ "name": "summarize-text", "about": "Summarizes text" })
+        );
+    }
+
+    #[tokio::test]
+    async fn deletions_name_the_announcement() {
+        let (announcer, relay) = announcer();
+        let created = published(
+            &announcer,
+            &relay,
+            &announcer.handler_information(&plugin()),
+        )
+        .await;
+        let address = format!("31990:{}:42", created.pubkey);
+
+        let event = published(
+            &announcer,
+            &relay,
+            &announcer.deletion(42, Some(&created.id)),
+        )
+        .await;
+        assert_eq!(event.kind, DELETION);
+        assert_eq!(event.pubkey, created.pubkey);
+        assert_eq!(event.content, "plugin deleted from the registry");
+        assert_eq!(
+            tags(&event),
+            [
+                vec!["e", &created.id],
+                vec!["a", &address],
+                vec!["k", "31990"],
+            ]
+        );
+
+        // Plugins announced before their events were kept are still
+        // retracted by address.
+        let event = published(&announcer, &relay, &announcer.deletion(42, None)).await;
+        assert_eq!(tags(&event), [vec!["a", &address], vec!["k", "31990"]]);
+    }
+}
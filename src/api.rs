@@ -0,0 +1,450 @@
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        Path, Query, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::account::{self, require_api_auth, Authenticated, Credentials, Session, User};
+use crate::error::{JsonError, RegistryError};
+use crate::pagination::ListParams;
+use crate::plugin::{Plugin, PluginNew, PluginPatch};
+use crate::search::PluginMatch;
+use crate::taxonomy::Facets;
+use crate::token::{ApiToken, ApiTokenNew, Scope};
+use crate::upload::WasmUpload;
+use crate::version::{parse_version_req, PluginVersion, PluginVersionNew, Verification};
+use crate::webhook::{Webhook, WebhookNew};
+use crate::{ingest_new_plugin, inspect_patch, AppState};
+
+pub fn router(state: AppState) -> Router<AppState> {
+    // Changes to plugins, tokens and webhooks need a session or API token.
+    let protected = Router::new()
+        .route("/plugins", get(list_plugins).post(create_plugin))
+        .route(
+            "/plugins/:id",
+            get(get_plugin)
+                .put(replace_plugin)
+                .patch(patch_plugin)
+                .delete(delete_plugin),
+        )
+        .route(
+            "/plugins/:id/versions",
+            get(list_versions).post(publish_version),
+        )
+        .route("/tokens", get(list_tokens).post(create_token))
+        .route("/tokens/:id", delete(revoke_token))
+        .route("/webhooks", get(list_webhooks).post(create_webhook))
+        .route("/webhooks/:id", delete(delete_webhook))
+        .route("/webhooks/:id/deliveries", get(list_deliveries))
+        .route("/webhooks/:id/pings", post(ping_webhook))
+        .route_layer(middleware::from_fn_with_state(state, require_api_auth));
+
+    Router::new()
+        .merge(protected)
+        .route("/plugins/:id/versions/:version", get(get_version))
+        .route("/plugins/:id/resolve", get(resolve_version))
+        .route("/plugins/:id/verify", get(verify_plugin))
+        .route("/users", post(register))
+        .route("/user", get(current_user))
+        .route("/sessions", post(create_session).delete(delete_session))
+}
+
+/// A page of plugins in `?sort=` order, or with `?q=` the best matches of a
+/// full text search, each with its `rank` and highlighted `snippet`. `?tag=`
+/// and `?category=` narrow either down, and `facets` counts the matching
+/// plugins per category and tag. The next page is linked both as
+/// `next_cursor` and in a `Link` header.
+async fn list_plugins(
+    State(state): State<AppState>,
+    params: Result<Query<ListParams>, QueryRejection>,
+) -> Result<Response, JsonError> {
+    let Query(params) = params?;
+    let filters = params.filters();
+    let sort = params.sort()?;
+    let cursor = params.cursor()?;
+    let limit = params.limit(&state.config);
+    let facets = Facets::load(&state.db, &filters).await?;
+
+    let (plugins, next_cursor) = if filters.query.is_empty() {
+        let page = Plugin::page(&state.db, &filters, sort, cursor.as_ref(), limit).await?;
+        (json!(page.plugins), page.next_cursor)
+    } else {
+        let page = PluginMatch::search(&state.db, &filters, cursor.as_ref(), limit).await?;
+        (json!(page.matches), page.next_cursor)
+    };
+    let body = Json(json!({
+        "plugins": plugins,
+        "next_cursor": next_cursor,
+        "facets": facets,
+    }));
+
+    Ok(match &next_cursor {
+        Some(next_cursor) => {
+            let link = format!(
+                "</api/v1/plugins?{}>; rel=\"next\"",
+                params.next(&state.config, next_cursor)
+            );
+            ([(header::LINK, link)], body).into_response()
+        }
+        None => body.into_response(),
+    })
+}
+
+async fn get_plugin(
+    State(state): State<AppState>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<Json<Plugin>, JsonError> {
+    let Path(id) = id?;
+
+    Ok(Json(Plugin::find(&state.db, id).await?))
+}
+
+async fn create_plugin(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    upload: Result<WasmUpload<PluginNew>, RegistryError>,
+) -> Result<Response, JsonError> {
+    let user = user?.scoped(Scope::Publish)?;
+    let WasmUpload {
+        fields: mut payload,
+        wasm_file,
+    } = upload?;
+
+    let artifact = ingest_new_plugin(&state, &mut payload, wasm_file).await?;
+    let plugin = Plugin::create(&state.db, user.id, payload, &artifact).await?;
+
+    let location = format!("/api/v1/plugins/{}", plugin.id);
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, location)],
+        Json(plugin),
+    )
+        .into_response())
+}
+
+async fn replace_plugin(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+    payload: Result<Json<PluginNew>, JsonRejection>,
+) -> Result<Json<Plugin>, JsonError> {
+    let user = user?.scoped(Scope::Publish)?;
+    let Path(id) = id?;
+    let Json(mut payload) = payload?;
+
+    user.plugin_to_modify(&state.db, id).await?;
+    payload.validate(&state.config)?;
+    let artifact = state.artifacts.ingest_url(&payload.wasm_url).await?;
+    payload.verify_signature(&artifact)?;
+    let plugin = Plugin::replace(&state.db, id, payload, &artifact).await?;
+
+    Ok(Json(plugin))
+}
+
+async fn patch_plugin(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+    payload: Result<Json<PluginPatch>, JsonRejection>,
+) -> Result<Json<Plugin>, JsonError> {
+    let user = user?.scoped(Scope::Publish)?;
+    let Path(id) = id?;
+    let Json(payload) = payload?;
+
+    user.plugin_to_modify(&state.db, id).await?;
+    payload.validate(&state.config)?;
+    let artifact = inspect_patch(&state, id, &payload).await?;
+    let plugin = Plugin::update(&state.db, id, payload, artifact.as_ref()).await?;
+
+    Ok(Json(plugin))
+}
+
+async fn delete_plugin(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<StatusCode, JsonError> {
+    let user = user?.scoped(Scope::Yank)?;
+    let Path(id) = id?;
+
+    user.plugin_to_modify(&state.db, id).await?;
+    Plugin::delete(&state.db, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_versions(
+    State(state): State<AppState>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<Json<serde_json::Value>, JsonError> {
+    let Path(id) = id?;
+
+    Plugin::find(&state.db, id).await?;
+    let versions = PluginVersion::for_plugin(&state.db, id).await?;
+
+    Ok(Json(json!({ "versions": versions })))
+}
+
+async fn get_version(
+    State(state): State<AppState>,
+    path: Result<Path<(i32, String)>, PathRejection>,
+) -> Result<Json<PluginVersion>, JsonError> {
+    let Path((id, version)) = path?;
+
+    Ok(Json(PluginVersion::find(&state.db, id, &version).await?))
+}
+
+async fn publish_version(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+    payload: Result<Json<PluginVersionNew>, JsonRejection>,
+) -> Result<Response, JsonError> {
+    let user = user?.scoped(Scope::Publish)?;
+    let Path(id) = id?;
+    let Json(payload) = payload?;
+
+    let plugin = user.plugin_to_modify(&state.db, id).await?;
+    payload.validate(&state.config)?;
+    let artifact = state.artifacts.ingest_url(&payload.wasm_url).await?;
+    payload.verify_signature(&plugin, &artifact)?;
+    let release = PluginVersion::publish(&state.db, id, payload, &artifact).await?;
+
+    let location = format!("/api/v1/plugins/{}/versions/{}", id, release.version);
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, location)],
+        Json(release),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+struct ResolveQuery {
+    req: String,
+}
+
+/// Resolves a semver requirement such as `^1.2` to the highest matching
+/// release, for agents that pin version ranges.
+async fn resolve_version(
+    State(state): State<AppState>,
+    id: Result<Path<i32>, PathRejection>,
+    query: Result<Query<ResolveQuery>, QueryRejection>,
+) -> Result<Json<PluginVersion>, JsonError> {
+    let Path(id) = id?;
+    let Query(query) = query?;
+    let req = parse_version_req(&query.req)?;
+
+    Plugin::find(&state.db, id).await?;
+
+    Ok(Json(PluginVersion::resolve(&state.db, id, &req).await?))
+}
+
+#[derive(Deserialize)]
+struct VerifyQuery {
+    version: Option<String>,
+}
+
+/// Re-checks that a release, the current one unless `?version=` is given,
+/// is signed by the plugin's publisher. The signed `manifest` is included so
+/// agents can check the signature themselves.
+async fn verify_plugin(
+    State(state): State<AppState>,
+    id: Result<Path<i32>, PathRejection>,
+    query: Result<Query<VerifyQuery>, QueryRejection>,
+) -> Result<Json<serde_json::Value>, JsonError> {
+    let Path(id) = id?;
+    let Query(query) = query?;
+
+    let plugin = Plugin::find(&state.db, id).await?;
+    let version = query.version.as_deref().unwrap_or(&plugin.version);
+    let release = PluginVersion::find(&state.db, id, version).await?;
+    let Verification {
+        npub,
+        manifest,
+        signature,
+        verified,
+    } = release.verification(&plugin);
+
+    Ok(Json(json!({
+        "plugin_id": plugin.id,
+        "name": plugin.name,
+        "version": release.version,
+        "sha256": release.sha256,
+        "npub": npub,
+        "manifest": manifest,
+        "signature": signature,
+        "verified": verified,
+    })))
+}
+
+async fn register(
+    State(state): State<AppState>,
+    payload: Result<Json<Credentials>, JsonRejection>,
+) -> Result<Response, JsonError> {
+    let Json(payload) = payload?;
+
+    payload.validate(&state.config)?;
+    let user = User::register(&state.db, &payload).await?;
+
+    Ok((StatusCode::CREATED, Json(user)).into_response())
+}
+
+async fn current_user(user: Result<Authenticated, RegistryError>) -> Result<Json<User>, JsonError> {
+    let user = user?.scoped(Scope::Read)?;
+
+    Ok(Json(user))
+}
+
+/// Logs in, returning a token to send as `Authorization: Bearer <token>`.
+async fn create_session(
+    State(state): State<AppState>,
+    payload: Result<Json<Credentials>, JsonRejection>,
+) -> Result<Response, JsonError> {
+    let Json(payload) = payload?;
+
+    let user = User::authenticate(&state.db, &payload).await?;
+    let session = Session::start(&state.db, &state.config, user.id).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "token": session.token,
+            "expires_at": session.expires_at,
+            "user": user,
+        })),
+    )
+        .into_response())
+}
+
+/// Logs out, revoking the token the request was made with.
+async fn delete_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    user: Result<Authenticated, RegistryError>,
+) -> Result<StatusCode, JsonError> {
+    user?;
+
+    if let Some(token) = account::session_token(&headers) {
+        Session::end(&state.db, token).await?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The logged in user's API tokens, without the secrets.
+async fn list_tokens(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+) -> Result<Json<serde_json::Value>, JsonError> {
+    let user = user?.session()?;
+    let tokens = ApiToken::for_user(&state.db, user.id).await?;
+
+    Ok(Json(json!({ "tokens": tokens })))
+}
+
+/// Creates an API token. The response is the only time `token` is shown.
+async fn create_token(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    payload: Result<Json<ApiTokenNew>, JsonRejection>,
+) -> Result<Response, JsonError> {
+    let user = user?.session()?;
+    let Json(payload) = payload?;
+
+    payload.validate(&state.config)?;
+    let token = ApiToken::create(&state.db, user.id, payload).await?;
+
+    Ok((StatusCode::CREATED, Json(token)).into_response())
+}
+
+async fn revoke_token(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<StatusCode, JsonError> {
+    let user = user?.session()?;
+    let Path(id) = id?;
+
+    ApiToken::delete(&state.db, user.id, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The logged in user's webhooks, without their secrets. Tokens need the
+/// read scope, as for every private read.
+async fn list_webhooks(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+) -> Result<Json<serde_json::Value>, JsonError> {
+    let user = user?.scoped(Scope::Read)?;
+    let webhooks = Webhook::for_user(&state.db, user.id).await?;
+
+    Ok(Json(json!({ "webhooks": webhooks })))
+}
+
+/// Adds a webhook. The response is the only time `secret` is shown.
+async fn create_webhook(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    payload: Result<Json<WebhookNew>, JsonRejection>,
+) -> Result<Response, JsonError> {
+    let user = user?.session()?;
+    let Json(payload) = payload?;
+
+    payload.validate(&state.config)?;
+    let webhook = Webhook::create(&state.db, user.id, payload).await?;
+
+    Ok((StatusCode::CREATED, Json(webhook)).into_response())
+}
+
+async fn delete_webhook(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<StatusCode, JsonError> {
+    let user = user?.session()?;
+    let Path(id) = id?;
+
+    Webhook::delete(&state.db, user.id, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The webhook's latest deliveries, newest first.
+async fn list_deliveries(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<Json<serde_json::Value>, JsonError> {
+    let user = user?.scoped(Scope::Read)?;
+    let Path(id) = id?;
+
+    let webhook = Webhook::find(&state.db, user.id, id).await?;
+    let deliveries = webhook.deliveries(&state.db).await?;
+
+    Ok(Json(json!({ "deliveries": deliveries })))
+}
+
+/// Queues a `ping` delivery to the webhook.
+async fn ping_webhook(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<Response, JsonError> {
+    let user = user?.session()?;
+    let Path(id) = id?;
+
+    let webhook = Webhook::find(&state.db, user.id, id).await?;
+    let delivery = webhook.ping(&state.db).await?;
+
+    Ok((StatusCode::ACCEPTED, Json(delivery)).into_response())
+}
@@ -0,0 +1,224 @@
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{rejection::PathRejection, Path, State},
+    http::header,
+    response::IntoResponse,
+};
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::error::RegistryError;
+use crate::plugin::Plugin;
+use crate::upload::{WasmFile, WASM_FILE_FIELD};
+use crate::wasm::{inspect, ModuleInfo, WasmFetcher};
+use crate::AppState;
+
+/// A WASM module that passed inspection, identified by the SHA-256 digest of
+/// its bytes.
+pub struct Artifact {
+    pub sha256: String,
+    pub module_info: ModuleInfo,
+}
+
+/// Storage for artifact bytes, addressed by their SHA-256 digest.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn put(&self, sha256: &str, bytes: &[u8]) -> std::io::Result<()>;
+
+    async fn get(&self, sha256: &str) -> std::io::Result<Option<Vec<u8>>>;
+}
+
+/// Keeps blobs on the local filesystem as `<root>/<first two hex chars>/<digest>`.
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, sha256: &str) -> PathBuf {
+        self.root.join(&sha256[..2]).join(sha256)
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, sha256: &str, bytes: &[u8]) -> std::io::Result<()> {
+        let path = self.path(sha256);
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(());
+        }
+
+        let dir = path.parent().expect("blob paths always have a parent");
+        tokio::fs::create_dir_all(dir).await?;
+
+        // Write to a temporary file first so readers never see a partial blob.
+        let tmp = dir.join(format!("{}.tmp", sha256));
+        tokio::fs::write(&tmp, bytes).await?;
+        tokio::fs::rename(&tmp, &path).await
+    }
+
+    async fn get(&self, sha256: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(sha256)).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Turns remote urls, uploads or raw bytes into inspected, digested
+/// artifacts, keeping a copy in the blob store when one is configured.
+#[derive(Clone)]
+pub struct Artifacts {
+    config: Arc<Config>,
+    fetcher: WasmFetcher,
+    store: Option<Arc<dyn BlobStore>>,
+}
+
+impl Artifacts {
+    pub fn new(config: Arc<Config>) -> Self {
+        let store = config
+            .store_artifacts
+            .then(|| Arc::new(LocalBlobStore::new(&config.artifact_dir)) as Arc<dyn BlobStore>);
+
+        Self {
+            fetcher: WasmFetcher::new(&config),
+            config,
+            store,
+        }
+    }
+
+    /// Urls pointing back at this registry's `/artifacts` are read straight
+    /// from the store instead of going over the network.
+    pub async fn ingest_url(&self, url: &str) -> Result<Artifact, RegistryError> {
+        let bytes = match self.config.artifact_digest(url) {
+            Some(sha256) => self.get(sha256).await?.ok_or_else(|| {
+                RegistryError::validation("wasm_url", "points at an unknown registry artifact")
+            })?,
+            None => self.fetcher.fetch(url).await?,
+        };
+
+        self.ingest("wasm_url", &bytes).await
+    }
+
+    pub async fn ingest_upload(&self, file: WasmFile) -> Result<Artifact, RegistryError> {
+        let invalid = |message: String| RegistryError::validation(WASM_FILE_FIELD, message);
+
+        if self.store.is_none() {
+            return Err(invalid(
+                "cannot be uploaded, this registry does not store artifacts".to_owned(),
+            ));
+        }
+        if !file.file_name.ends_with(".wasm") {
+            return Err(invalid("must be a .wasm file".to_owned()));
+        }
+        let bytes = file.bytes.ok_or_else(|| {
+            invalid(format!(
+                "is larger than {} bytes",
+                self.config.upload_max_size
+            ))
+        })?;
+
+        self.ingest(WASM_FILE_FIELD, &bytes).await
+    }
+
+    /// Inspection problems are reported against `field`, the input the
+    /// bytes came from.
+    pub async fn ingest(&self, field: &str, bytes: &[u8]) -> Result<Artifact, RegistryError> {
+        let module_info =
+            inspect(bytes).map_err(|message| RegistryError::validation(field, message))?;
+        let sha256 = hex::encode(Sha256::digest(bytes));
+
+        if let Some(store) = &self.store {
+            store.put(&sha256, bytes).await.map_err(storage_error)?;
+        }
+
+        Ok(Artifact {
+            sha256,
+            module_info,
+        })
+    }
+
+    /// Bytes of a published module, preferring the stored copy. A module
+    /// that has to be downloaded again must still match its pinned digest.
+    pub async fn load(&self, sha256: Option<&str>, url: &str) -> Result<Vec<u8>, RegistryError> {
+        if let Some(sha256) = sha256 {
+            if let Some(bytes) = self.get(sha256).await? {
+                return Ok(bytes);
+            }
+        }
+
+        let bytes = match self.config.artifact_digest(url) {
+            Some(digest) => self
+                .get(digest)
+                .await?
+                .ok_or_else(|| RegistryError::NotFound(format!("artifact {} not found", digest)))?,
+            None => self.fetcher.fetch(url).await?,
+        };
+
+        if let Some(sha256) = sha256 {
+            if hex::encode(Sha256::digest(&bytes)) != sha256 {
+                return Err(RegistryError::Conflict(format!(
+                    "the module at {} no longer matches its pinned digest {}",
+                    url, sha256
+                )));
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    pub async fn get(&self, sha256: &str) -> Result<Option<Vec<u8>>, RegistryError> {
+        if !is_sha256(sha256) {
+            return Ok(None);
+        }
+
+        match &self.store {
+            Some(store) => store.get(sha256).await.map_err(storage_error),
+            None => Ok(None),
+        }
+    }
+}
+
+fn storage_error(err: std::io::Error) -> RegistryError {
+    RegistryError::Storage(err.to_string())
+}
+
+fn is_sha256(digest: &str) -> bool {
+    digest.len() == 64
+        && digest
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Serves a stored artifact and counts the download. The digest is the cache
+/// key, so responses never change and can be cached forever.
+pub async fn serve_artifact(
+    State(state): State<AppState>,
+    sha256: Result<Path<String>, PathRejection>,
+) -> Result<impl IntoResponse, RegistryError> {
+    let Path(sha256) = sha256?;
+    let not_found = || RegistryError::NotFound(format!("artifact {} not found", sha256));
+
+    let bytes = state.artifacts.get(&sha256).await?.ok_or_else(not_found)?;
+    Plugin::record_download(&state.db, &sha256).await?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/wasm".to_owned()),
+            (header::ETAG, format!("\"{}\"", sha256)),
+            (
+                header::CACHE_CONTROL,
+                "public, max-age=31536000, immutable".to_owned(),
+            ),
+        ],
+        bytes,
+    ))
+}
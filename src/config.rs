@@ -0,0 +1,180 @@
+use shuttle_secrets::SecretStore;
+
+/// Registry settings, read from the Shuttle secret store (`Secrets.toml`
+/// locally). Every key is optional and falls back to the default below.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// `ALLOWED_URL_SCHEMES`: comma separated schemes accepted for plugin
+    /// and webhook urls.
+    pub allowed_url_schemes: Vec<String>,
+    /// `DESCRIPTION_MIN_LENGTH`
+    pub description_min_length: usize,
+    /// `DESCRIPTION_MAX_LENGTH`
+    pub description_max_length: usize,
+    /// `WASM_MAX_SIZE`: largest module, in bytes, the registry will download.
+    pub wasm_max_size: usize,
+    /// `WASM_FETCH_TIMEOUT_SECS`
+    pub wasm_fetch_timeout_secs: u64,
+    /// `STORE_ARTIFACTS`: keep a content-addressed copy of every published
+    /// module and serve it from `/artifacts/:sha256`.
+    pub store_artifacts: bool,
+    /// `ARTIFACT_DIR`: where the local blob store keeps artifacts.
+    pub artifact_dir: String,
+    /// `UPLOAD_MAX_SIZE`: largest `.wasm` file, in bytes, accepted as an
+    /// upload.
+    pub upload_max_size: usize,
+    /// `PUBLIC_URL`: base url the registry is reachable at, used to build
+    /// the url of uploaded artifacts.
+    pub public_url: String,
+    /// `RUN_FUEL_LIMIT`: fuel, roughly one unit per instruction, a plugin
+    /// call may consume.
+    pub run_fuel_limit: u64,
+    /// `RUN_MEMORY_LIMIT`: bytes of linear and host memory a plugin call may
+    /// use.
+    pub run_memory_limit: usize,
+    /// `RUN_TIMEOUT_MS`: wall-clock limit for a plugin call.
+    pub run_timeout_ms: u64,
+    /// `PAGE_SIZE`: plugins per page when `?limit=` isn't given.
+    pub page_size: usize,
+    /// `SESSION_TTL_HOURS`: how long a login lasts.
+    pub session_ttl_hours: i64,
+    /// `STREAM_CAPACITY`: plugin updates buffered for live subscribers. One
+    /// that falls further behind misses updates and is told to resync.
+    pub stream_capacity: usize,
+    /// `NOSTR_SECRET_KEY`: `nsec1...` or hex key the registry signs its
+    /// announcements of plugin changes with.
+    pub nostr_secret_key: Option<String>,
+    /// `NOSTR_RELAYS`: comma separated relay urls to announce plugin changes
+    /// to. Nothing is announced without relays and a key.
+    pub nostr_relays: Vec<String>,
+    /// `WEBHOOK_TIMEOUT_SECS`: how long a webhook gets to answer a delivery.
+    pub webhook_timeout_secs: u64,
+    /// `WEBHOOK_MAX_ATTEMPTS`: deliveries are given up on after this many
+    /// failed attempts.
+    pub webhook_max_attempts: i32,
+    /// `WEBHOOK_RETRY_BASE_SECS`: wait before retrying a failed delivery,
+    /// doubled after every further failure.
+    pub webhook_retry_base_secs: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            allowed_url_schemes: vec!["https".to_owned()],
+            description_min_length: 10,
+            description_max_length: 500,
+            wasm_max_size: 10 * 1024 * 1024,
+            wasm_fetch_timeout_secs: 10,
+            store_artifacts: true,
+            artifact_dir: "artifacts".to_owned(),
+            upload_max_size: 10 * 1024 * 1024,
+            public_url: "http://localhost:8000".to_owned(),
+            run_fuel_limit: 100_000_000,
+            run_memory_limit: 64 * 1024 * 1024,
+            run_timeout_ms: 5_000,
+            page_size: 25,
+            session_ttl_hours: 24 * 30,
+            stream_capacity: 256,
+            nostr_secret_key: None,
+            nostr_relays: Vec::new(),
+            webhook_timeout_secs: 10,
+            webhook_max_attempts: 8,
+            webhook_retry_base_secs: 30,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_secrets(secrets: &SecretStore) -> Self {
+        let default = Self::default();
+
+        Self {
+            allowed_url_schemes: secrets
+                .get("ALLOWED_URL_SCHEMES")
+                .map(|schemes| {
+                    schemes
+                        .split(',')
+                        .map(|scheme| scheme.trim().to_ascii_lowercase())
+                        .filter(|scheme| !scheme.is_empty())
+                        .collect()
+                })
+                .unwrap_or(default.allowed_url_schemes),
+            description_min_length: parse_secret(secrets, "DESCRIPTION_MIN_LENGTH")
+                .unwrap_or(default.description_min_length),
+            description_max_length: parse_secret(secrets, "DESCRIPTION_MAX_LENGTH")
+                .unwrap_or(default.description_max_length),
+            wasm_max_size: parse_secret(secrets, "WASM_MAX_SIZE").unwrap_or(default.wasm_max_size),
+            wasm_fetch_timeout_secs: parse_secret(secrets, "WASM_FETCH_TIMEOUT_SECS")
+                .unwrap_or(default.wasm_fetch_timeout_secs),
+            store_artifacts: parse_secret(secrets, "STORE_ARTIFACTS")
+                .unwrap_or(default.store_artifacts),
+            artifact_dir: secrets.get("ARTIFACT_DIR").unwrap_or(default.artifact_dir),
+            upload_max_size: parse_secret(secrets, "UPLOAD_MAX_SIZE")
+                .unwrap_or(default.upload_max_size),
+            public_url: secrets
+                .get("PUBLIC_URL")
+                .map(|url| url.trim_end_matches('/').to_owned())
+                .unwrap_or(default.public_url),
+            run_fuel_limit: parse_secret(secrets, "RUN_FUEL_LIMIT")
+                .unwrap_or(default.run_fuel_limit),
+            run_memory_limit: parse_secret(secrets, "RUN_MEMORY_LIMIT")
+                .unwrap_or(default.run_memory_limit),
+            run_timeout_ms: parse_secret(secrets, "RUN_TIMEOUT_MS")
+                .unwrap_or(default.run_timeout_ms),
+            page_size: parse_secret(secrets, "PAGE_SIZE").unwrap_or(default.page_size),
+            session_ttl_hours: parse_secret(secrets, "SESSION_TTL_HOURS")
+                .unwrap_or(default.session_ttl_hours),
+            stream_capacity: parse_secret(secrets, "STREAM_CAPACITY")
+                .filter(|capacity| *capacity > 0)
+                .unwrap_or(default.stream_capacity),
+            nostr_secret_key: secrets.get("NOSTR_SECRET_KEY"),
+            nostr_relays: secrets
+                .get("NOSTR_RELAYS")
+                .map(|relays| {
+                    relays
+                        .split(',')
+                        .map(|relay| relay.trim().to_owned())
+                        .filter(|relay| !relay.is_empty())
+                        .collect()
+                })
+                .unwrap_or(default.nostr_relays),
+            webhook_timeout_secs: parse_secret(secrets, "WEBHOOK_TIMEOUT_SECS")
+                .unwrap_or(default.webhook_timeout_secs),
+            webhook_max_attempts: parse_secret(secrets, "WEBHOOK_MAX_ATTEMPTS")
+                .filter(|attempts| *attempts > 0)
+                .unwrap_or(default.webhook_max_attempts),
+            webhook_retry_base_secs: parse_secret(secrets, "WEBHOOK_RETRY_BASE_SECS")
+                .unwrap_or(default.webhook_retry_base_secs),
+        }
+    }
+
+    /// Session cookies are only sent over https when the registry is served
+    /// over https.
+    pub fn secure_cookies(&self) -> bool {
+        self.public_url.starts_with("https://")
+    }
+
+    /// Url the registry serves the artifact with this digest from.
+    pub fn artifact_url(&self, sha256: &str) -> String {
+        format!("{}/artifacts/{}", self.public_url, sha256)
+    }
+
+    /// Digest of the artifact `url` points at, if it is served by this
+    /// registry.
+    pub fn artifact_digest<'a>(&self, url: &'a str) -> Option<&'a str> {
+        url.strip_prefix(&self.public_url)?
+            .strip_prefix("/artifacts/")
+    }
+}
+
+fn parse_secret<T: std::str::FromStr>(secrets: &SecretStore, key: &str) -> Option<T> {
+    let value = secrets.get(key)?;
+
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            eprintln!("Ignoring secret {} with invalid value {:?}", key, value);
+            None
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use askama::Template;
+use axum::{
+    extract::{
+        multipart::{MultipartError, MultipartRejection},
+        rejection::{BytesRejection, FormRejection, JsonRejection, PathRejection, QueryRejection},
+        ws::rejection::WebSocketUpgradeRejection,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::postgres::PgDatabaseError;
+
+/// Everything that can go wrong while serving a registry request.
+///
+/// Returned directly from the HTMX handlers, where it renders as an error
+/// fragment, and wrapped in [`JsonError`] by the JSON API.
+#[derive(Debug)]
+pub enum RegistryError {
+    Database(sqlx::Error),
+    Storage(String),
+    NotFound(String),
+    Validation(Vec<FieldError>),
+    Conflict(String),
+    /// The request needs a logged in user and didn't come with one.
+    Unauthorized(String),
+    /// The user is logged in but not allowed to do this.
+    Forbidden(String),
+    BadRequest(StatusCode, String),
+    /// A bug or crash on our side, logged and reported without details.
+    Internal(String),
+}
+
+impl RegistryError {
+    pub fn plugin_not_found(id: i32) -> Self {
+        Self::NotFound(format!("plugin {} not found", id))
+    }
+
+    pub fn validation(field: &str, message: impl Into<String>) -> Self {
+        Self::Validation(vec![FieldError::new(field, message)])
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Database(_) | Self::Storage(_) | Self::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::BadRequest(status, _) => *status,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Database(_) => "database",
+            Self::Storage(_) => "storage",
+            Self::NotFound(_) => "not_found",
+            Self::Validation(_) => "validation",
+            Self::Conflict(_) => "conflict",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::BadRequest(..) => "bad_request",
+            Self::Internal(_) => "internal",
+        }
+    }
+
+    /// Message that is safe to show to clients. Database, storage and internal
+    /// errors are logged and replaced with a generic message so we don't leak
+    /// internals.
+    pub fn message(&self) -> String {
+        match self {
+            Self::Database(_) => "something went wrong talking to the database".to_owned(),
+            Self::Storage(_) => "something went wrong storing the artifact".to_owned(),
+            Self::Internal(_) => "something went wrong on our side".to_owned(),
+            Self::Validation(fields) => fields
+                .iter()
+                .map(|error| format!("{}: {}", error.field, error.message))
+                .collect::<Vec<_>>()
+                .join("; "),
+            Self::NotFound(message)
+            | Self::Conflict(message)
+            | Self::Unauthorized(message)
+            | Self::Forbidden(message)
+            | Self::BadRequest(_, message) => message.clone(),
+        }
+    }
+
+    fn fields(&self) -> &[FieldError] {
+        match self {
+            Self::Validation(fields) => fields,
+            _ => &[],
+        }
+    }
+
+    fn log(&self) {
+        match self {
+            Self::Database(err) => eprintln!("Database error: {}", err),
+            Self::Storage(err) => eprintln!("Storage error: {}", err),
+            Self::Internal(err) => eprintln!("Internal error: {}", err),
+            _ => {}
+        }
+    }
+}
+
+impl From<sqlx::Error> for RegistryError {
+    fn from(err: sqlx::Error) -> Self {
+        if let sqlx::Error::RowNotFound = err {
+            return Self::NotFound("record not found".to_owned());
+        }
+
+        if let Some(db_err) = err.as_database_error() {
+            // https://www.postgresql.org/docs/current/errcodes-appendix.html
+            match db_err.code().as_deref() {
+                Some("23505") => return Self::Conflict("record already exists".to_owned()),
+                Some("23503") => return Self::Conflict("record is still referenced".to_owned()),
+                Some("23502") | Some("23514") => {
+                    let field = db_err
+                        .try_downcast_ref::<PgDatabaseError>()
+                        .and_then(|pg_err| pg_err.column())
+                        .unwrap_or("record");
+                    return Self::validation(field, db_err.message());
+                }
+                _ => {}
+            }
+        }
+
+        Self::Database(err)
+    }
+}
+
+/// A validation failure tied to a single input field.
+#[derive(Clone, Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_owned(),
+            message: message.into(),
+        }
+    }
+}
+
+impl From<FormRejection> for RegistryError {
+    fn from(rejection: FormRejection) -> Self {
+        Self::BadRequest(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for RegistryError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::BadRequest(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for RegistryError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::BadRequest(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for RegistryError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::BadRequest(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<BytesRejection> for RegistryError {
+    fn from(rejection: BytesRejection) -> Self {
+        Self::BadRequest(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<MultipartRejection> for RegistryError {
+    fn from(rejection: MultipartRejection) -> Self {
+        Self::BadRequest(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<WebSocketUpgradeRejection> for RegistryError {
+    fn from(rejection: WebSocketUpgradeRejection) -> Self {
+        Self::BadRequest(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<MultipartError> for RegistryError {
+    fn from(err: MultipartError) -> Self {
+        Self::BadRequest(err.status(), err.body_text())
+    }
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate<'a> {
+    status: u16,
+    message: String,
+    fields: &'a [FieldError],
+}
+
+/// A whole page for errors on full page loads, where there is no `#errors`
+/// region to put the fragment in.
+#[derive(Template)]
+#[template(path = "error_page.html")]
+struct ErrorPageTemplate<'a> {
+    status: u16,
+    reason: &'static str,
+    message: String,
+    fields: &'a [FieldError],
+}
+
+impl RegistryError {
+    /// Renders the error as a page of its own rather than a fragment.
+    pub fn into_page(self) -> Response {
+        self.log();
+
+        let status = self.status();
+        let template = ErrorPageTemplate {
+            status: status.as_u16(),
+            reason: status.canonical_reason().unwrap_or("Error"),
+            message: self.message(),
+            fields: self.fields(),
+        };
+
+        (status, template).into_response()
+    }
+}
+
+impl IntoResponse for RegistryError {
+    fn into_response(self) -> Response {
+        self.log();
+
+        let status = self.status();
+        let template = ErrorTemplate {
+            status: status.as_u16(),
+            message: self.message(),
+            fields: self.fields(),
+        };
+
+        // Errors land in the page-wide `#errors` region rather than wherever
+        // the triggering element was going to swap its response.
+        (
+            status,
+            [("HX-Retarget", "#errors"), ("HX-Reswap", "innerHTML")],
+            template,
+        )
+            .into_response()
+    }
+}
+
+/// JSON rendering of a [`RegistryError`] for API clients:
+/// `{"error": {"status": 404, "code": "not_found", "message": "..."}}`.
+/// Validation errors additionally list `fields: [{"field", "message"}]`.
+#[derive(Debug)]
+pub struct JsonError(pub RegistryError);
+
+impl<E: Into<RegistryError>> From<E> for JsonError {
+    fn from(err: E) -> Self {
+        Self(err.into())
+    }
+}
+
+impl IntoResponse for JsonError {
+    fn into_response(self) -> Response {
+        self.0.log();
+
+        let status = self.0.status();
+        let mut body = json!({
+            "error": {
+                "status": status.as_u16(),
+                "code": self.0.code(),
+                "message": self.0.message(),
+            }
+        });
+        if let RegistryError::Validation(fields) = &self.0 {
+            body["error"]["fields"] = json!(fields);
+        }
+
+        (status, Json(body)).into_response()
+    }
+}
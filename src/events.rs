@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::json;
+use sqlx::postgres::PgListener;
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use tokio::sync::OnceCell;
+
+use crate::error::RegistryError;
+use crate::plugin::Plugin;
+use crate::webhook;
+use crate::{MutationKind, PluginUpdate, PluginsStream};
+
+/// Postgres channel plugin changes are announced on. Every registry instance
+/// listens on it, so subscribers see changes made through any replica.
+pub const CHANNEL: &str = "plugin_updates";
+
+/// Transaction level advisory lock serializing writes to the event log.
+const EVENT_LOG_LOCK: i64 = 0x6576656e7473;
+
+/// Changes whose plugin [`Snapshots`] keeps, enough for subscribers that
+/// are a little behind.
+const SNAPSHOTS_KEPT: usize = 256;
+
+/// Records a change in the event log, queues its webhook deliveries and
+/// announces it to every instance. Postgres only delivers the notification
+/// once the transaction making the change commits, and drops all of it if it
+/// rolls back.
+pub async fn notify(
+    conn: &mut PgConnection,
+    mutation_kind: MutationKind,
+    id: i32,
+) -> Result<(), RegistryError> {
+    // Replay relies on events committing in id order, so one transaction at
+    // a time gets to log an event.
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(EVENT_LOG_LOCK)
+        .execute(&mut *conn)
+        .await?;
+
+    let update = sqlx::query_as::<_, PluginUpdate>(
+        "INSERT INTO plugin_events (plugin_id, mutation_kind) VALUES ($1, $2)
+         RETURNING id AS event_id, mutation_kind, plugin_id AS id",
+    )
+    .bind(id)
+    .bind(mutation_kind)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    webhook::enqueue(&mut *conn, &update).await?;
+
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(CHANNEL)
+        .bind(json!(update).to_string())
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Changes logged after event `event_id`, oldest first.
+pub async fn since(
+    db: impl PgExecutor<'_>,
+    event_id: i64,
+) -> Result<Vec<PluginUpdate>, RegistryError> {
+    let updates = sqlx::query_as::<_, PluginUpdate>(
+        "SELECT id AS event_id, mutation_kind, plugin_id AS id FROM plugin_events
+         WHERE id > $1 ORDER BY id",
+    )
+    .bind(event_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(updates)
+}
+
+/// Relays notifications from every instance to this instance's subscribers.
+/// Notifications sent while the connection is down are lost, so when it
+/// drops the listener starts over on a new one and replays the changes
+/// logged since the last one relayed.
+pub async fn listen(db: &PgPool, tx: PluginsStream) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(db).await?;
+    listener.listen(CHANNEL).await?;
+    let mut last_seen: i64 = sqlx::query_scalar("SELECT coalesce(max(id), 0) FROM plugin_events")
+        .fetch_one(db)
+        .await?;
+
+    let db = db.clone();
+    tokio::spawn(async move {
+        loop {
+            match listener.try_recv().await {
+                Ok(Some(notification)) => {
+                    match serde_json::from_str::<PluginUpdate>(notification.payload()) {
+                        Ok(update) => relay(&tx, &mut last_seen, update),
+                        Err(err) => eprintln!(
+                            "Ignoring plugin update {:?}: {}",
+                            notification.payload(),
+                            err
+                        ),
+                    }
+                }
+                Ok(None) => {
+                    eprintln!("Lost the connection listening for plugin updates");
+                    listener = listen_again(&db, &tx, &mut last_seen).await;
+                }
+                Err(err) => {
+                    eprintln!("Couldn't listen for plugin updates: {}", err);
+                    listener = listen_again(&db, &tx, &mut last_seen).await;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Listens on a new connection, then catches up on what was logged after
+/// `last_seen`. Listening first means nothing falls in between; changes
+/// that show up in both are only relayed once. Retries until it works.
+async fn listen_again(db: &PgPool, tx: &PluginsStream, last_seen: &mut i64) -> PgListener {
+    loop {
+        let caught_up = async {
+            let mut listener = PgListener::connect_with(db).await?;
+            listener.listen(CHANNEL).await?;
+            for update in since(db, *last_seen).await? {
+                relay(tx, last_seen, update);
+            }
+            Ok::<_, RegistryError>(listener)
+        };
+
+        match caught_up.await {
+            Ok(listener) => return listener,
+            Err(err) => {
+                eprintln!("Couldn't catch up on plugin updates: {:?}", err);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// Plugins as they were loaded for recent changes, shared by every stream on
+/// this instance so each change is looked up once however many subscribers
+/// there are.
+#[derive(Clone, Default)]
+pub struct Snapshots {
+    loaded: Arc<Mutex<BTreeMap<i64, Arc<Snapshot>>>>,
+}
+
+/// The plugin as loaded for one change, `None` if it was gone by then.
+type Snapshot = OnceCell<Option<Arc<Plugin>>>;
+
+impl Snapshots {
+    /// The plugin `update` is about, as first loaded after the change.
+    /// Missing for deletions and for plugins deleted since.
+    pub async fn plugin(
+        &self,
+        db: &PgPool,
+        update: &PluginUpdate,
+    ) -> Result<Option<Arc<Plugin>>, RegistryError> {
+        if update.mutation_kind == MutationKind::Delete {
+            return Ok(None);
+        }
+
+        // Subscribers asking at the same time wait for the one lookup. A
+        // failed lookup isn't kept, so the next subscriber tries again.
+        let snapshot = self.snapshot(update.event_id);
+        let plugin = snapshot
+            .get_or_try_init(|| async {
+                match Plugin::find(db, update.id).await {
+                    Ok(plugin) => Ok(Some(Arc::new(plugin))),
+                    Err(RegistryError::NotFound(_)) => Ok(None),
+                    Err(err) => Err(err),
+                }
+            })
+            .await?;
+
+        Ok(plugin.clone())
+    }
+
+    /// The snapshot for `event_id`, making room by forgetting the oldest.
+    fn snapshot(&self, event_id: i64) -> Arc<Snapshot> {
+        let mut loaded = self.loaded.lock().expect("snapshots lock poisoned");
+        let snapshot = loaded.entry(event_id).or_default().clone();
+        while loaded.len() > SNAPSHOTS_KEPT {
+            loaded.pop_first();
+        }
+
+        snapshot
+    }
+}
+
+/// Passes `update` on unless it was already replayed. Events are logged and
+/// notified in id order, so anything at or before `last_seen` has been sent.
+fn relay(tx: &PluginsStream, last_seen: &mut i64, update: PluginUpdate) {
+    if update.event_id > *last_seen {
+        *last_seen = update.event_id;
+        notify_subscribers(tx, update);
+    }
+}
+
+fn notify_subscribers(tx: &PluginsStream, update: PluginUpdate) {
+    let id = update.id;
+    let verb = match update.mutation_kind {
+        MutationKind::Create => "created",
+        MutationKind::Update => "updated",
+        MutationKind::Delete => "deleted",
+    };
+
+    if tx.send(update).is_err() {
+        eprintln!(
+            "Record with ID {} was {} but nobody's listening to the stream!",
+            id, verb
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::broadcast;
+
+    fn update(event_id: i64) -> PluginUpdate {
+        PluginUpdate {
+            event_id,
+            mutation_kind: MutationKind::Update,
+            id: 1,
+        }
+    }
+
+    /// A pool that fails fast, for checking nothing gets looked up.
+    fn no_database() -> PgPool {
+        sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("postgres://registry@127.0.0.1:1/registry")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_looked_up_once() {
+        let snapshots = Snapshots::default();
+        let db = no_database();
+
+        // Loaded for the first subscriber, as deleted since.
+        assert!(snapshots.snapshot(7).set(None).is_ok());
+        for _ in 0..3 {
+            assert!(snapshots.plugin(&db, &update(7)).await.unwrap().is_none());
+        }
+
+        let deletion = PluginUpdate {
+            mutation_kind: MutationKind::Delete,
+            ..update(8)
+        };
+        assert!(snapshots.plugin(&db, &deletion).await.unwrap().is_none());
+        assert!(snapshots.plugin(&db, &update(9)).await.is_err());
+    }
+
+    #[test]
+    fn only_recent_snapshots_are_kept() {
+        let snapshots = Snapshots::default();
+        for event_id in 1..=300 {
+            snapshots.snapshot(event_id);
+        }
+
+        let loaded = snapshots.loaded.lock().unwrap();
+        assert_eq!(loaded.len(), SNAPSHOTS_KEPT);
+        assert_eq!(
+            loaded.keys().next(),
+            Some(&(300 - SNAPSHOTS_KEPT as i64 + 1))
+        );
+    }
+
+    #[test]
+    fn replayed_changes_are_relayed_once() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let mut last_seen = 3;
+
+        // Replayed from the log after reconnecting, then heard again live.
+        for event_id in [4, 5, 4, 5, 6] {
+            relay(&tx, &mut last_seen, update(event_id));
+        }
+
+        let relayed: Vec<i64> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|update| update.event_id)
+            .collect();
+        assert_eq!(relayed, [4, 5, 6]);
+        assert_eq!(last_seen, 6);
+    }
+}
@@ -1,90 +1,250 @@
 use askama::Template;
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::{sse::Event, IntoResponse, Response, Sse},
-    routing::{delete, get},
-    Extension, Form, Router,
+    extract::{
+        rejection::{FormRejection, PathRejection, QueryRejection},
+        DefaultBodyLimit, Path, Query, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    middleware,
+    response::{sse::Event, IntoResponse, Redirect, Response, Sse},
+    routing::{delete, get, post},
+    Extension, Form, Json, Router,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use shuttle_secrets::SecretStore;
 use sqlx::PgPool;
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::broadcast::{channel, Sender};
-use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 use tokio_stream::{Stream, StreamExt as _};
 
+mod account;
+mod announce;
+mod api;
+mod artifact;
+mod config;
+mod error;
+mod events;
+mod metrics;
+mod nostr;
+mod pagination;
+mod plugin;
+mod runtime;
+mod search;
+mod taxonomy;
+mod token;
+mod upload;
+mod validation;
+mod version;
+mod wasm;
+mod webhook;
+mod websocket;
+
+use account::{require_auth, Authenticated, Credentials, CurrentUser, Session, User};
+use announce::Announcer;
+use artifact::{serve_artifact, Artifact, Artifacts};
+use config::Config;
+use error::{FieldError, JsonError, RegistryError};
+use metrics::{metrics, StreamMetrics};
+use pagination::ListParams;
+use plugin::{Plugin, PluginNew, PluginPatch};
+use runtime::{call_plugin, run_plugin, CallOutcome, Runtime};
+use search::{PluginMatch, Snippet};
+use taxonomy::Facets;
+use token::{ApiToken, ApiTokenNew, NewApiToken, Scope};
+use upload::{WasmFile, WasmUpload};
+use validation::FieldErrors;
+use version::{PluginVersion, PluginVersionNew, Verification};
+use wasm::ModuleInfo;
+use webhook::{NewWebhook, Webhook, WebhookDelivery, WebhookNew};
+
 pub type PluginsStream = Sender<PluginUpdate>;
 
-#[derive(Clone, Serialize, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "plugin_mutation", rename_all = "lowercase")]
 pub enum MutationKind {
     Create,
+    Update,
     Delete,
 }
 
-#[derive(Clone, Serialize, Debug)]
+impl MutationKind {
+    /// Name of the server-sent event the change is streamed as.
+    pub fn event_name(self) -> &'static str {
+        match self {
+            Self::Create => "create",
+            Self::Update => "update",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, sqlx::FromRow)]
 pub struct PluginUpdate {
+    /// Position in the event log, see [`events::since`].
+    event_id: i64,
     mutation_kind: MutationKind,
+    /// The plugin that changed.
     id: i32,
 }
 
 #[derive(Clone)]
 struct AppState {
     db: PgPool,
-}
-
-#[derive(sqlx::FromRow, Serialize, Deserialize)]
-struct Plugin {
-    id: i32,
-    description: String,
-    wasm_url: String,
-}
-
-#[derive(sqlx::FromRow, Serialize, Deserialize)]
-struct PluginNew {
-    description: String,
-    wasm_url: String,
+    config: Arc<Config>,
+    artifacts: Artifacts,
+    runtime: Runtime,
+    metrics: Arc<StreamMetrics>,
+    snapshots: events::Snapshots,
 }
 
 #[shuttle_runtime::main]
-async fn main(#[shuttle_shared_db::Postgres] db: PgPool) -> shuttle_axum::ShuttleAxum {
+async fn main(
+    #[shuttle_shared_db::Postgres] db: PgPool,
+    #[shuttle_secrets::Secrets] secrets: SecretStore,
+) -> shuttle_axum::ShuttleAxum {
     sqlx::migrate!()
         .run(&db)
         .await
         .expect("Looks like something went wrong with migrations :(");
 
-    let (plugin_tx, _plugin_rx) = channel::<PluginUpdate>(10);
-    let state = AppState { db };
+    let config = Config::from_secrets(&secrets);
+    let config = Arc::new(config);
+    let (plugin_tx, _plugin_rx) = channel::<PluginUpdate>(config.stream_capacity);
+    // Leave room for the text fields that accompany an upload.
+    let body_limit = DefaultBodyLimit::max(config.upload_max_size + 64 * 1024);
+    let state = AppState {
+        db,
+        artifacts: Artifacts::new(config.clone()),
+        runtime: Runtime::new(&config),
+        config,
+        metrics: Arc::default(),
+        snapshots: events::Snapshots::default(),
+    };
+
+    events::listen(&state.db, plugin_tx.clone())
+        .await
+        .expect("Looks like listening for plugin updates failed :(");
+    if let Some(announcer) = Announcer::from_config(&state.config) {
+        println!(
+            "Announcing plugin changes to {} as {}",
+            state.config.nostr_relays.join(", "),
+            announcer.npub()
+        );
+        announcer.spawn(state.db.clone(), &plugin_tx, state.metrics.clone());
+    }
+    webhook::spawn(state.db.clone(), &state.config, &plugin_tx);
+
+    // Changes to plugins, tokens and webhooks need a login or an API token.
+    let protected = Router::new()
+        .route("/plugins", get(fetch_plugins).post(create_plugin))
+        .route(
+            "/plugins/:id",
+            get(get_plugin)
+                .put(replace_plugin)
+                .patch(patch_plugin)
+                .delete(delete_plugin),
+        )
+        .route("/plugins/:id/versions", post(publish_version))
+        .route("/tokens", get(tokens_page).post(create_token))
+        .route("/tokens/:id", delete(revoke_token))
+        .route("/webhooks", get(webhooks_page).post(create_webhook))
+        .route("/webhooks/:id", get(webhook_page).delete(delete_webhook))
+        .route("/webhooks/:id/deliveries", get(webhook_deliveries))
+        .route("/webhooks/:id/pings", post(ping_webhook))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_auth));
 
     let router = Router::new()
         .route("/", get(home))
         .route("/stream", get(stream))
         .route("/styles.css", get(styles))
-        .route("/plugins", get(fetch_plugins).post(create_plugin))
-        .route("/plugins/:id", delete(delete_plugin))
+        .route("/metrics", get(metrics))
+        .merge(protected)
+        .route("/plugins/:id/row", get(plugin_row))
+        .route("/plugins/:id/edit", get(edit_plugin))
+        .route("/plugins/:id/call/:function", post(call_plugin))
+        .route(
+            "/plugins/:id/playground",
+            get(playground).post(run_playground),
+        )
         .route("/plugins/stream", get(handle_plugin_stream))
+        .route("/ws", get(websocket::handle_socket))
+        .route("/artifacts/:sha256", get(serve_artifact))
+        .route("/account", get(account_nav))
+        .route("/signup", get(signup_page).post(signup))
+        .route("/login", get(login_page).post(login))
+        .route("/logout", post(logout))
+        .nest("/api/v1", api::router(state.clone()))
         .with_state(state)
+        .layer(body_limit)
         .layer(Extension(plugin_tx));
 
     Ok(router.into())
 }
 
 async fn home() -> impl IntoResponse {
-    HelloTemplate
+    HelloTemplate {
+        form: PluginNew::default(),
+        errors: FieldErrors::default(),
+    }
 }
 
 async fn stream() -> impl IntoResponse {
     StreamTemplate
 }
 
-async fn fetch_plugins(State(state): State<AppState>) -> impl IntoResponse {
-    let plugins = sqlx::query_as::<_, Plugin>("SELECT * FROM PLUGINS")
-        .fetch_all(&state.db)
-        .await
-        .unwrap();
+/// The plugin table, or with `?q=` the best search matches, narrowed down by
+/// `?tag=` and `?category=`. Requests for a later page (`?cursor=`) only get
+/// the next rows to append.
+async fn fetch_plugins(
+    State(state): State<AppState>,
+    params: Result<Query<ListParams>, QueryRejection>,
+) -> Result<Response, RegistryError> {
+    let Query(params) = params?;
+    let filters = params.filters();
+    let sort = params.sort()?;
+    let cursor = params.cursor()?;
+    let limit = params.limit(&state.config);
 
-    PluginRecords { plugins }
+    let mut snippets = HashMap::new();
+    let (plugins, next_cursor) = if filters.query.is_empty() {
+        let page = Plugin::page(&state.db, &filters, sort, cursor.as_ref(), limit).await?;
+        (page.plugins, page.next_cursor)
+    } else {
+        let page = PluginMatch::search(&state.db, &filters, cursor.as_ref(), limit).await?;
+        let mut plugins = Vec::new();
+        for found in page.matches {
+            snippets.insert(found.plugin.id, found.snippet);
+            plugins.push(found.plugin);
+        }
+        (plugins, page.next_cursor)
+    };
+    let more = next_cursor
+        .map(|next_cursor| format!("/plugins?{}", params.next(&state.config, &next_cursor)));
+
+    if cursor.is_some() {
+        return Ok(PluginRowsTemplate {
+            plugins,
+            snippets,
+            more,
+        }
+        .into_response());
+    }
+
+    let facets = Facets::load(&state.db, &filters).await?;
+
+    Ok(PluginRecords {
+        plugins,
+        snippets,
+        facets,
+        params,
+        more,
+    }
+    .into_response())
 }
 
 pub async fn styles() -> impl IntoResponse {
@@ -97,73 +257,740 @@ pub async fn styles() -> impl IntoResponse {
 
 async fn create_plugin(
     State(state): State<AppState>,
-    Extension(tx): Extension<PluginsStream>,
-    Form(form): Form<PluginNew>,
-) -> impl IntoResponse {
-    let plugin = sqlx::query_as::<_, Plugin>(
-      "INSERT INTO PLUGINS (description, wasm_url) VALUES ($1, $2) RETURNING id, description, wasm_url",
-  )
-  .bind(form.description)
-  .bind(form.wasm_url)
-  .fetch_one(&state.db)
-  .await
-  .unwrap();
-
-    if tx
-        .send(PluginUpdate {
-            mutation_kind: MutationKind::Create,
-            id: plugin.id,
+    user: Result<Authenticated, RegistryError>,
+    upload: Result<WasmUpload<PluginNew>, RegistryError>,
+) -> Result<Response, RegistryError> {
+    let user = user?.scoped(Scope::Publish)?;
+    let WasmUpload {
+        fields: mut form,
+        wasm_file,
+    } = upload?;
+
+    let artifact = match ingest_new_plugin(&state, &mut form, wasm_file).await {
+        Ok(artifact) => artifact,
+        Err(RegistryError::Validation(errors)) => return Ok(invalid_form(form, errors)),
+        Err(err) => return Err(err),
+    };
+
+    let plugin = match Plugin::create(&state.db, user.id, form.clone(), &artifact).await {
+        Ok(plugin) => plugin,
+        Err(RegistryError::Conflict(message)) => {
+            return Ok(invalid_form(form, vec![FieldError::new("name", message)]))
+        }
+        Err(err) => return Err(err),
+    };
+
+    Ok(PluginNewTemplate { plugin }.into_response())
+}
+
+/// Re-renders the add form with inline errors in place of the submitted one,
+/// instead of appending a row to the table.
+fn invalid_form(form: PluginNew, errors: Vec<FieldError>) -> Response {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        [("HX-Retarget", "#add-form"), ("HX-Reswap", "outerHTML")],
+        PluginFormTemplate {
+            form,
+            errors: FieldErrors(errors),
+        },
+    )
+        .into_response()
+}
+
+/// Renders the plugin detail page, or the plugin as JSON when the client
+/// asks for `application/json`.
+async fn get_plugin(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    id: Result<Path<i32>, PathRejection>,
+) -> Response {
+    let plugin = match id {
+        Ok(Path(id)) => Plugin::find(&state.db, id).await,
+        Err(rejection) => Err(rejection.into()),
+    };
+
+    if accepts_json(&headers) {
+        return match plugin {
+            Ok(plugin) => Json(plugin).into_response(),
+            Err(err) => JsonError(err).into_response(),
+        };
+    }
+
+    let page = match plugin {
+        Ok(plugin) => PluginVersion::for_plugin(&state.db, plugin.id)
+            .await
+            .map(|versions| PluginDetailTemplate::new(plugin, versions)),
+        Err(err) => Err(err),
+    };
+
+    match page {
+        Ok(page) => page.into_response(),
+        Err(err) if is_htmx(&headers) => err.into_response(),
+        Err(err) => err.into_page(),
+    }
+}
+
+async fn playground(
+    State(state): State<AppState>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<PlaygroundTemplate, RegistryError> {
+    let Path(id) = id?;
+
+    let plugin = Plugin::find(&state.db, id).await?;
+    let release = PluginVersion::find(&state.db, id, &plugin.version).await?;
+    let functions = release
+        .module_info
+        .map(|module_info| {
+            module_info
+                .0
+                .exports
+                .into_iter()
+                .filter(|export| export.kind == "func")
+                .map(|export| export.name)
+                .collect()
         })
-        .is_err()
-    {
-        eprintln!(
-            "Record with ID {} was created but nobody's listening to the stream!",
-            plugin.id
-        );
+        .unwrap_or_default();
+
+    Ok(PlaygroundTemplate { plugin, functions })
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum InputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Deserialize)]
+struct PlaygroundRun {
+    function: String,
+    input: String,
+    format: InputFormat,
+}
+
+async fn run_playground(
+    State(state): State<AppState>,
+    id: Result<Path<i32>, PathRejection>,
+    form: Result<Form<PlaygroundRun>, FormRejection>,
+) -> Result<PlaygroundResultTemplate, RegistryError> {
+    let Path(id) = id?;
+    let Form(form) = form?;
+
+    if form.format == InputFormat::Json {
+        serde_json::from_str::<serde_json::Value>(&form.input).map_err(|err| {
+            RegistryError::validation("input", format!("is not valid JSON: {}", err))
+        })?;
+    }
+
+    let outcome = run_plugin(&state, id, form.function, form.input.into_bytes()).await?;
+
+    // JSON in, JSON out is the common case, so pretty print it.
+    let output = match form.format {
+        InputFormat::Json => serde_json::from_str::<serde_json::Value>(&outcome.output)
+            .ok()
+            .and_then(|output| serde_json::to_string_pretty(&output).ok()),
+        InputFormat::Text => None,
+    }
+    .unwrap_or_else(|| outcome.output.clone());
+
+    Ok(PlaygroundResultTemplate { outcome, output })
+}
+
+async fn publish_version(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+    form: Result<Form<PluginVersionNew>, FormRejection>,
+) -> Result<PluginVersionTemplate, RegistryError> {
+    let user = user?.scoped(Scope::Publish)?;
+    let Path(id) = id?;
+    let Form(form) = form?;
+
+    let plugin = user.plugin_to_modify(&state.db, id).await?;
+    form.validate(&state.config)?;
+    let artifact = state.artifacts.ingest_url(&form.wasm_url).await?;
+    form.verify_signature(&plugin, &artifact)?;
+    let release = PluginVersion::publish(&state.db, id, form, &artifact).await?;
+
+    Ok(PluginVersionTemplate { plugin, release })
+}
+
+/// Inline edit row swapped in place of a plugin's table row.
+async fn edit_plugin(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<PluginEditTemplate, RegistryError> {
+    let user = user?.scoped(Scope::Publish)?;
+    let Path(id) = id?;
+    let plugin = user.plugin_to_modify(&state.db, id).await?;
+
+    Ok(PluginEditTemplate { plugin })
+}
+
+/// Plain table row for a plugin, used to cancel an inline edit.
+async fn plugin_row(
+    State(state): State<AppState>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<PluginNewTemplate, RegistryError> {
+    let Path(id) = id?;
+    let plugin = Plugin::find(&state.db, id).await?;
+
+    Ok(PluginNewTemplate { plugin })
+}
+
+async fn replace_plugin(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+    form: Result<Form<PluginNew>, FormRejection>,
+) -> Result<PluginNewTemplate, RegistryError> {
+    let user = user?.scoped(Scope::Publish)?;
+    let Path(id) = id?;
+    let Form(mut form) = form?;
+
+    user.plugin_to_modify(&state.db, id).await?;
+    form.validate(&state.config)?;
+    let artifact = state.artifacts.ingest_url(&form.wasm_url).await?;
+    form.verify_signature(&artifact)?;
+    let plugin = Plugin::replace(&state.db, id, form, &artifact).await?;
+
+    Ok(PluginNewTemplate { plugin })
+}
+
+async fn patch_plugin(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+    form: Result<Form<PluginPatch>, FormRejection>,
+) -> Result<PluginNewTemplate, RegistryError> {
+    let user = user?.scoped(Scope::Publish)?;
+    let Path(id) = id?;
+    let Form(form) = form?;
+
+    user.plugin_to_modify(&state.db, id).await?;
+    form.validate(&state.config)?;
+    let artifact = inspect_patch(&state, id, &form).await?;
+    let plugin = Plugin::update(&state.db, id, form, artifact.as_ref()).await?;
+
+    Ok(PluginNewTemplate { plugin })
+}
+
+/// Validates a new plugin and resolves its artifact. An uploaded file takes
+/// precedence over `wasm_url`, which is replaced by the registry-served url
+/// of the upload. A publisher's signature is checked against the artifact.
+async fn ingest_new_plugin(
+    state: &AppState,
+    form: &mut PluginNew,
+    wasm_file: Option<WasmFile>,
+) -> Result<Artifact, RegistryError> {
+    let artifact = match wasm_file {
+        Some(wasm_file) => {
+            let artifact = state.artifacts.ingest_upload(wasm_file).await?;
+            form.wasm_url = state.config.artifact_url(&artifact.sha256);
+            form.validate(&state.config)?;
+            artifact
+        }
+        None => {
+            form.validate(&state.config)?;
+            state.artifacts.ingest_url(&form.wasm_url).await?
+        }
+    };
+    form.verify_signature(&artifact)?;
+
+    Ok(artifact)
+}
+
+/// Patches that move a plugin to another version or artifact need the
+/// resulting module inspected and digested before its release is recorded.
+async fn inspect_patch(
+    state: &AppState,
+    id: i32,
+    patch: &PluginPatch,
+) -> Result<Option<Artifact>, RegistryError> {
+    if patch.version.is_none() && patch.wasm_url.is_none() {
+        return Ok(None);
     }
 
-    PluginNewTemplate { plugin }
+    let wasm_url = match &patch.wasm_url {
+        Some(wasm_url) => wasm_url.clone(),
+        None => Plugin::find(&state.db, id).await?.wasm_url,
+    };
+
+    state.artifacts.ingest_url(&wasm_url).await.map(Some)
+}
+
+fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.contains("application/json"))
+        .unwrap_or(false)
+}
+
+/// Whether the request was made by HTMX, rather than being a page load.
+fn is_htmx(headers: &HeaderMap) -> bool {
+    headers.contains_key("HX-Request")
 }
 
 async fn delete_plugin(
     State(state): State<AppState>,
-    Path(id): Path<i32>,
-    Extension(tx): Extension<PluginsStream>,
-) -> impl IntoResponse {
-    sqlx::query("DELETE FROM PLUGINS WHERE ID = $1")
-        .bind(id)
-        .execute(&state.db)
-        .await
-        .unwrap();
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<StatusCode, RegistryError> {
+    let user = user?.scoped(Scope::Yank)?;
+    let Path(id) = id?;
+
+    user.plugin_to_modify(&state.db, id).await?;
+    Plugin::delete(&state.db, id).await?;
 
-    if tx
-        .send(PluginUpdate {
-            mutation_kind: MutationKind::Delete,
-            id,
+    Ok(StatusCode::OK)
+}
+
+/// Login state shown in every page's header.
+async fn account_nav(
+    user: Result<CurrentUser, RegistryError>,
+) -> Result<AccountNavTemplate, RegistryError> {
+    let CurrentUser(user) = user?;
+
+    Ok(AccountNavTemplate { user })
+}
+
+async fn signup_page() -> impl IntoResponse {
+    SignupTemplate
+}
+
+async fn signup(
+    State(state): State<AppState>,
+    form: Result<Form<Credentials>, FormRejection>,
+) -> Result<Response, RegistryError> {
+    let Form(form) = form?;
+
+    form.validate(&state.config)?;
+    let user = User::register(&state.db, &form).await?;
+
+    logged_in(&state, &user).await
+}
+
+async fn login_page() -> impl IntoResponse {
+    LoginTemplate
+}
+
+async fn login(
+    State(state): State<AppState>,
+    form: Result<Form<Credentials>, FormRejection>,
+) -> Result<Response, RegistryError> {
+    let Form(form) = form?;
+    let user = User::authenticate(&state.db, &form).await?;
+
+    logged_in(&state, &user).await
+}
+
+/// Starts a session for `user` and sends the browser back to the plugin
+/// list.
+async fn logged_in(state: &AppState, user: &User) -> Result<Response, RegistryError> {
+    let session = Session::start(&state.db, &state.config, user.id).await?;
+
+    Ok((
+        [
+            (header::SET_COOKIE, session.cookie(&state.config)),
+            (
+                header::HeaderName::from_static("hx-redirect"),
+                "/".to_owned(),
+            ),
+        ],
+        StatusCode::OK,
+    )
+        .into_response())
+}
+
+async fn logout(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, RegistryError> {
+    if let Some(token) = account::session_token(&headers) {
+        Session::end(&state.db, token).await?;
+    }
+
+    Ok((
+        [
+            (header::SET_COOKIE, Session::removal_cookie(&state.config)),
+            (
+                header::HeaderName::from_static("hx-redirect"),
+                "/".to_owned(),
+            ),
+        ],
+        StatusCode::OK,
+    )
+        .into_response())
+}
+
+/// Lists the user's API tokens with a form to create more. Only reachable
+/// after logging in with a password.
+async fn tokens_page(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+) -> Result<Response, RegistryError> {
+    let user = match user {
+        Ok(user) => user.session()?,
+        Err(RegistryError::Unauthorized(_)) => return Ok(Redirect::to("/login").into_response()),
+        Err(err) => return Err(err),
+    };
+    let tokens = ApiToken::for_user(&state.db, user.id).await?;
+
+    Ok(TokensTemplate {
+        tokens,
+        created: None,
+    }
+    .into_response())
+}
+
+/// The token form's scopes are separate checkboxes.
+#[derive(Deserialize)]
+struct TokenForm {
+    name: String,
+    read: Option<String>,
+    publish: Option<String>,
+    yank: Option<String>,
+    #[serde(default, deserialize_with = "plugin::empty_string_as_none")]
+    expires_in_days: Option<String>,
+}
+
+impl TryFrom<TokenForm> for ApiTokenNew {
+    type Error = RegistryError;
+
+    fn try_from(form: TokenForm) -> Result<Self, Self::Error> {
+        let scopes = [
+            (Scope::Read, form.read),
+            (Scope::Publish, form.publish),
+            (Scope::Yank, form.yank),
+        ]
+        .into_iter()
+        .filter_map(|(scope, checked)| checked.map(|_| scope))
+        .collect();
+        let expires_in_days = form
+            .expires_in_days
+            .map(|days| {
+                days.parse()
+                    .map_err(|_| RegistryError::validation("expires_in_days", "must be a number"))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            name: form.name,
+            scopes,
+            expires_in_days,
         })
-        .is_err()
-    {
-        eprintln!(
-            "Record with ID {} was deleted but nobody's listening to the stream!",
-            id
-        );
     }
+}
+
+/// Creates a token and re-renders the token list with the new token shown
+/// above it, the only time it can be copied.
+async fn create_token(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    form: Result<Form<TokenForm>, FormRejection>,
+) -> Result<TokenListTemplate, RegistryError> {
+    let user = user?.session()?;
+    let Form(form) = form?;
+
+    let new = ApiTokenNew::try_from(form)?;
+    new.validate(&state.config)?;
+    let created = ApiToken::create(&state.db, user.id, new).await?;
+    let tokens = ApiToken::for_user(&state.db, user.id).await?;
+
+    Ok(TokenListTemplate {
+        tokens,
+        created: Some(created),
+    })
+}
+
+async fn revoke_token(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<StatusCode, RegistryError> {
+    let user = user?.session()?;
+    let Path(id) = id?;
+
+    ApiToken::delete(&state.db, user.id, id).await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Lists the user's webhooks with a form to add more. Only reachable after
+/// logging in with a password.
+async fn webhooks_page(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+) -> Result<Response, RegistryError> {
+    let user = match user {
+        Ok(user) => user.session()?,
+        Err(RegistryError::Unauthorized(_)) => return Ok(Redirect::to("/login").into_response()),
+        Err(err) => return Err(err),
+    };
+    let webhooks = Webhook::for_user(&state.db, user.id).await?;
+
+    Ok(WebhooksTemplate {
+        webhooks,
+        created: None,
+    }
+    .into_response())
+}
+
+/// The webhook form's events are separate checkboxes.
+#[derive(Deserialize)]
+struct WebhookForm {
+    url: String,
+    create: Option<String>,
+    update: Option<String>,
+    delete: Option<String>,
+    #[serde(default, deserialize_with = "plugin::empty_string_as_none")]
+    plugin_id: Option<String>,
+    #[serde(default, deserialize_with = "plugin::empty_string_as_none")]
+    tag: Option<String>,
+    #[serde(default, deserialize_with = "plugin::empty_string_as_none")]
+    owner: Option<String>,
+}
+
+impl TryFrom<WebhookForm> for WebhookNew {
+    type Error = RegistryError;
 
-    StatusCode::OK
+    fn try_from(form: WebhookForm) -> Result<Self, Self::Error> {
+        let events = [
+            (MutationKind::Create, form.create),
+            (MutationKind::Update, form.update),
+            (MutationKind::Delete, form.delete),
+        ]
+        .into_iter()
+        .filter_map(|(kind, checked)| checked.map(|_| kind))
+        .collect();
+        let plugin_id = form
+            .plugin_id
+            .map(|id| {
+                id.parse()
+                    .map_err(|_| RegistryError::validation("plugin_id", "must be a number"))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            url: form.url,
+            events,
+            plugin_id,
+            tag: form.tag,
+            owner: form.owner,
+        })
+    }
+}
+
+/// Adds a webhook and re-renders the list with its secret shown above it,
+/// the only time it can be copied.
+async fn create_webhook(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    form: Result<Form<WebhookForm>, FormRejection>,
+) -> Result<WebhookListTemplate, RegistryError> {
+    let user = user?.session()?;
+    let Form(form) = form?;
+
+    let new = WebhookNew::try_from(form)?;
+    new.validate(&state.config)?;
+    let created = Webhook::create(&state.db, user.id, new).await?;
+    let webhooks = Webhook::for_user(&state.db, user.id).await?;
+
+    Ok(WebhookListTemplate {
+        webhooks,
+        created: Some(created),
+    })
+}
+
+async fn delete_webhook(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<StatusCode, RegistryError> {
+    let user = user?.session()?;
+    let Path(id) = id?;
+
+    Webhook::delete(&state.db, user.id, id).await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// The webhook's delivery log.
+async fn webhook_page(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<Response, RegistryError> {
+    let user = match user {
+        Ok(user) => user.session()?,
+        Err(RegistryError::Unauthorized(_)) => return Ok(Redirect::to("/login").into_response()),
+        Err(err) => return Err(err),
+    };
+    let Path(id) = id?;
+
+    let webhook = Webhook::find(&state.db, user.id, id).await?;
+    let deliveries = webhook.deliveries(&state.db).await?;
+
+    Ok(WebhookTemplate {
+        webhook,
+        deliveries,
+    }
+    .into_response())
+}
+
+/// Just the delivery log, which the log page polls.
+async fn webhook_deliveries(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<WebhookDeliveriesTemplate, RegistryError> {
+    let user = user?.session()?;
+    let Path(id) = id?;
+
+    let webhook = Webhook::find(&state.db, user.id, id).await?;
+    let deliveries = webhook.deliveries(&state.db).await?;
+
+    Ok(WebhookDeliveriesTemplate {
+        webhook,
+        deliveries,
+    })
+}
+
+/// Queues a test delivery and re-renders the delivery log.
+async fn ping_webhook(
+    State(state): State<AppState>,
+    user: Result<Authenticated, RegistryError>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<WebhookDeliveriesTemplate, RegistryError> {
+    let user = user?.session()?;
+    let Path(id) = id?;
+
+    let webhook = Webhook::find(&state.db, user.id, id).await?;
+    webhook.ping(&state.db).await?;
+    let deliveries = webhook.deliveries(&state.db).await?;
+
+    Ok(WebhookDeliveriesTemplate {
+        webhook,
+        deliveries,
+    })
 }
 
 #[derive(Template)]
 #[template(path = "index.html")]
-struct HelloTemplate;
+struct HelloTemplate {
+    form: PluginNew,
+    errors: FieldErrors,
+}
+
+#[derive(Template)]
+#[template(path = "plugin_form.html")]
+struct PluginFormTemplate {
+    form: PluginNew,
+    errors: FieldErrors,
+}
 
 #[derive(Template)]
 #[template(path = "stream.html")]
 struct StreamTemplate;
 
+#[derive(Template)]
+#[template(path = "account_nav.html")]
+struct AccountNavTemplate {
+    user: Option<User>,
+}
+
+#[derive(Template)]
+#[template(path = "signup.html")]
+struct SignupTemplate;
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate;
+
+#[derive(Template)]
+#[template(path = "tokens.html")]
+struct TokensTemplate {
+    tokens: Vec<ApiToken>,
+    created: Option<NewApiToken>,
+}
+
+#[derive(Template)]
+#[template(path = "token_list.html")]
+struct TokenListTemplate {
+    tokens: Vec<ApiToken>,
+    /// The token just created, shown once.
+    created: Option<NewApiToken>,
+}
+
+#[derive(Template)]
+#[template(path = "webhooks.html")]
+struct WebhooksTemplate {
+    webhooks: Vec<Webhook>,
+    created: Option<NewWebhook>,
+}
+
+#[derive(Template)]
+#[template(path = "webhook_list.html")]
+struct WebhookListTemplate {
+    webhooks: Vec<Webhook>,
+    /// The webhook just added, whose secret is shown once.
+    created: Option<NewWebhook>,
+}
+
+#[derive(Template)]
+#[template(path = "webhook.html")]
+struct WebhookTemplate {
+    webhook: Webhook,
+    deliveries: Vec<WebhookDelivery>,
+}
+
+#[derive(Template)]
+#[template(path = "webhook_deliveries.html")]
+struct WebhookDeliveriesTemplate {
+    webhook: Webhook,
+    deliveries: Vec<WebhookDelivery>,
+}
+
 #[derive(Template)]
 #[template(path = "plugins.html")]
 struct PluginRecords {
     plugins: Vec<Plugin>,
+    /// Highlighted descriptions when listing search results.
+    snippets: HashMap<i32, Snippet>,
+    /// Plugin counts per category and tag under the current filters.
+    facets: Facets,
+    params: ListParams,
+    /// Url of the next page, if there is one.
+    more: Option<String>,
+}
+
+impl PluginRecords {
+    fn snippet(&self, id: &i32) -> Option<&Snippet> {
+        self.snippets.get(id)
+    }
+
+    fn filtered(&self) -> bool {
+        !self.params.query().is_empty()
+            || self.params.tag.is_some()
+            || self.params.category.is_some()
+    }
+}
+
+/// A later page of the plugin table.
+#[derive(Template)]
+#[template(path = "plugin_rows.html")]
+struct PluginRowsTemplate {
+    plugins: Vec<Plugin>,
+    snippets: HashMap<i32, Snippet>,
+    more: Option<String>,
+}
+
+impl PluginRowsTemplate {
+    fn snippet(&self, id: &i32) -> Option<&Snippet> {
+        self.snippets.get(id)
+    }
 }
 
 #[derive(Template)]
@@ -172,25 +999,171 @@ struct PluginNewTemplate {
     plugin: Plugin,
 }
 
-pub async fn handle_plugin_stream(
+/// A plugin change as swaps for any plugin table on the page. New plugins
+/// come as a row to insert, replacing the one their creator may already
+/// have; changed plugins replace their row out of band and deleted ones
+/// remove it.
+#[derive(Template)]
+#[template(path = "plugin_event.html")]
+struct PluginEventTemplate {
+    id: i32,
+    created: bool,
+    /// Missing once the plugin is deleted.
+    plugin: Option<Arc<Plugin>>,
+}
+
+#[derive(Template)]
+#[template(path = "plugin_edit.html")]
+struct PluginEditTemplate {
+    plugin: Plugin,
+}
+
+#[derive(Template)]
+#[template(path = "plugin_detail.html")]
+struct PluginDetailTemplate {
+    plugin: Plugin,
+    versions: Vec<PluginVersion>,
+    /// Inspection of the current version's module.
+    module_info: Option<ModuleInfo>,
+    /// Whether the current version is signed by the publisher.
+    verification: Option<Verification>,
+}
+
+impl PluginDetailTemplate {
+    fn new(plugin: Plugin, versions: Vec<PluginVersion>) -> Self {
+        let current = versions
+            .iter()
+            .find(|release| release.version == plugin.version);
+        let module_info = current
+            .and_then(|release| release.module_info.as_ref())
+            .map(|module_info| module_info.0.clone());
+        let verification = current.map(|release| release.verification(&plugin));
+
+        Self {
+            plugin,
+            versions,
+            module_info,
+            verification,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "playground.html")]
+struct PlaygroundTemplate {
+    plugin: Plugin,
+    /// Exported functions of the current version's module.
+    functions: Vec<String>,
+}
+
+#[derive(Template)]
+#[template(path = "playground_result.html")]
+struct PlaygroundResultTemplate {
+    outcome: CallOutcome,
+    output: String,
+}
+
+#[derive(Template)]
+#[template(path = "version.html")]
+struct PluginVersionTemplate {
+    plugin: Plugin,
+    release: PluginVersion,
+}
+
+/// Streams plugin changes as server-sent events named after the kind of
+/// change, each with its event log id. Browsers send the last id they saw as
+/// `Last-Event-ID` when they reconnect, and get the changes they missed
+/// first.
+async fn handle_plugin_stream(
+    State(state): State<AppState>,
     Extension(tx): Extension<PluginsStream>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, RegistryError> {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<i64>().ok());
+
+    // Subscribe before reading the log so nothing falls in between; changes
+    // that show up in both are only sent once.
     let rx = tx.subscribe();
+    let missed = match last_event_id {
+        Some(event_id) => events::since(&state.db, event_id).await?,
+        None => Vec::new(),
+    };
+    let replayed_up_to = missed
+        .last()
+        .map(|update| update.event_id)
+        .or(last_event_id);
 
-    let stream = BroadcastStream::new(rx);
+    // A subscriber that falls behind the channel has missed updates for
+    // good, so it is told to reload what it shows instead.
+    let metrics = state.metrics.clone();
+    let live = BroadcastStream::new(rx).filter_map(move |msg| match msg {
+        Ok(update) => replayed_up_to
+            .is_none_or(|event_id| update.event_id > event_id)
+            .then_some(Ok(update)),
+        Err(BroadcastStreamRecvError::Lagged(missed)) => {
+            metrics.record_lag(missed);
+            Some(Err(missed))
+        }
+    });
 
-    Sse::new(
-        stream
-            .map(|msg| {
-                let msg = msg.unwrap();
-                let json = format!("<div>{}</div>", json!(msg));
-                Event::default().data(json)
-            })
-            .map(Ok),
-    )
-    .keep_alive(
+    let (db, snapshots) = (state.db, state.snapshots);
+    let events =
+        futures_util::StreamExt::then(tokio_stream::iter(missed).map(Ok).chain(live), move |msg| {
+            let (db, snapshots) = (db.clone(), snapshots.clone());
+            async move {
+                let event = match msg {
+                    Ok(update) => update_event(&db, &snapshots, &update).await,
+                    Err(missed) => resync_event(format!("Missed {} changes", missed)),
+                };
+                Ok(event)
+            }
+        });
+
+    Ok(Sse::new(events).keep_alive(
         axum::response::sse::KeepAlive::new()
             .interval(Duration::from_secs(600))
             .text("keep-alive-text"),
-    )
+    ))
+}
+
+/// The change rendered as a [`PluginEventTemplate`]. The plugin is looked up
+/// once for every stream, and missing if deleted since, which a later event
+/// tells about anyway.
+async fn update_event(db: &PgPool, snapshots: &events::Snapshots, update: &PluginUpdate) -> Event {
+    let plugin = match snapshots.plugin(db, update).await {
+        Ok(plugin) => plugin,
+        Err(err) => {
+            eprintln!("Couldn't stream plugin {}: {:?}", update.id, err);
+            return resync_event(format!("Couldn't load plugin {}", update.id));
+        }
+    };
+
+    let html = match (PluginEventTemplate {
+        id: update.id,
+        created: matches!(update.mutation_kind, MutationKind::Create),
+        plugin,
+    })
+    .render()
+    {
+        Ok(html) => html,
+        Err(err) => {
+            eprintln!("Couldn't render plugin {}: {}", update.id, err);
+            return resync_event(format!("Couldn't show plugin {}", update.id));
+        }
+    };
+
+    Event::default()
+        .id(update.event_id.to_string())
+        .event(update.mutation_kind.event_name())
+        .data(html)
+}
+
+/// Tells the page to reload what it shows, having missed changes.
+fn resync_event(reason: String) -> Event {
+    Event::default()
+        .event("resync")
+        .data(format!("<div>{}, reload to catch up</div>", reason))
 }
@@ -0,0 +1,62 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+    Extension,
+};
+
+use crate::{AppState, PluginsStream};
+
+/// Counters for subscribers to plugin updates that fall behind the broadcast
+/// channel and miss updates.
+#[derive(Default)]
+pub struct StreamMetrics {
+    lagged: AtomicU64,
+    dropped_events: AtomicU64,
+}
+
+impl StreamMetrics {
+    pub fn record_lag(&self, missed: u64) {
+        self.lagged.fetch_add(1, Ordering::Relaxed);
+        self.dropped_events.fetch_add(missed, Ordering::Relaxed);
+    }
+}
+
+/// Metrics in the Prometheus text format.
+pub async fn metrics(
+    State(state): State<AppState>,
+    Extension(tx): Extension<PluginsStream>,
+) -> Response {
+    let stream = &state.metrics;
+    let mut body = String::new();
+
+    for (name, kind, help, value) in [
+        (
+            "registry_stream_subscribers",
+            "gauge",
+            "Subscribers to plugin updates on this instance.",
+            tx.receiver_count() as u64,
+        ),
+        (
+            "registry_stream_lagged_total",
+            "counter",
+            "Times a subscriber fell behind and missed plugin updates.",
+            stream.lagged.load(Ordering::Relaxed),
+        ),
+        (
+            "registry_stream_dropped_events_total",
+            "counter",
+            "Plugin updates missed by subscribers that fell behind.",
+            stream.dropped_events.load(Ordering::Relaxed),
+        ),
+    ] {
+        let _ = writeln!(body, "# HELP {} {}", name, help);
+        let _ = writeln!(body, "# TYPE {} {}", name, kind);
+        let _ = writeln!(body, "{} {}", name, value);
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
+}
@@ -0,0 +1,345 @@
+use bech32::{Bech32, Hrp};
+use chrono::Utc;
+use secp256k1::{schnorr::Signature, Keypair, Message, XOnlyPublicKey, SECP256K1};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::error::RegistryError;
+
+const NPUB: Hrp = Hrp::parse_unchecked("npub");
+const NSEC: Hrp = Hrp::parse_unchecked("nsec");
+
+/// Parses a Nostr public key given either as an `npub1...` string or as the
+/// 64 hex characters of the x-only key.
+pub fn parse_public_key(key: &str) -> Option<XOnlyPublicKey> {
+    let bytes = if key.starts_with("npub1") {
+        let (hrp, bytes) = bech32::decode(key).ok()?;
+        if hrp != NPUB {
+            return None;
+        }
+        bytes
+    } else {
+        hex::decode(key).ok()?
+    };
+
+    XOnlyPublicKey::from_slice(&bytes).ok()
+}
+
+/// Parses a secret key given either as an `nsec1...` string or as 64 hex
+/// characters.
+pub fn parse_secret_key(key: &str) -> Option<Keypair> {
+    let bytes = if key.starts_with("nsec1") {
+        let (hrp, bytes) = bech32::decode(key).ok()?;
+        if hrp != NSEC {
+            return None;
+        }
+        bytes
+    } else {
+        hex::decode(key).ok()?
+    };
+
+    Keypair::from_seckey_slice(SECP256K1, &bytes).ok()
+}
+
+/// NIP-19 `npub1...` encoding of a public key.
+pub fn npub(key: &XOnlyPublicKey) -> String {
+    bech32::encode::<Bech32>(NPUB, &key.serialize()).expect("public keys always fit in bech32")
+}
+
+/// What a publisher signs to vouch for a release: the plugin's name and
+/// version and the digest of its module.
+pub struct Manifest<'a> {
+    pub name: &'a str,
+    pub version: &'a str,
+    pub sha256: &'a str,
+}
+
+impl Manifest<'_> {
+    /// The exact text that is hashed and signed, a JSON array in the style of
+    /// a Nostr event serialization:
+    /// `["openagents-plugin",<name>,<version>,<sha256>]`.
+    pub fn serialize(&self) -> String {
+        json!(["openagents-plugin", self.name, self.version, self.sha256]).to_string()
+    }
+
+    /// SHA-256 of [`Manifest::serialize`], the message the BIP-340 Schnorr
+    /// signature is made over, just like a Nostr event id.
+    pub fn digest(&self) -> [u8; 32] {
+        Sha256::digest(self.serialize().as_bytes()).into()
+    }
+
+    /// Whether `signature`, 64 hex encoded bytes, is `npub`'s signature of
+    /// this manifest.
+    pub fn is_signed_by(&self, npub: &str, signature: &str) -> bool {
+        let Some(key) = parse_public_key(npub) else {
+            return false;
+        };
+        let Some(signature) = hex::decode(signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        else {
+            return false;
+        };
+
+        SECP256K1
+            .verify_schnorr(&signature, &Message::from_digest(self.digest()), &key)
+            .is_ok()
+    }
+
+    /// Checks the signature on a submission, returning the signer's npub.
+    pub fn verify(&self, public_key: &str, signature: &str) -> Result<String, RegistryError> {
+        let key = parse_public_key(public_key).ok_or_else(|| {
+            RegistryError::validation("npub", "is not an npub or hex encoded public key")
+        })?;
+        let npub = npub(&key);
+
+        if !self.is_signed_by(&npub, signature) {
+            return Err(RegistryError::validation(
+                "signature",
+                format!(
+                    "is not a valid signature of {} by {}",
+                    self.serialize(),
+                    npub
+                ),
+            ));
+        }
+
+        Ok(npub)
+    }
+}
+
+/// A signed Nostr event, as defined by NIP-01.
+#[derive(Clone, Debug, Serialize)]
+pub struct Event {
+    /// Hex SHA-256 of the event's serialization, which is what gets signed.
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: i64,
+    pub kind: u32,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+
+impl Event {
+    /// Builds an event created now and signs it with `keys`.
+    pub fn sign(keys: &Keypair, kind: u32, tags: Vec<Vec<String>>, content: String) -> Event {
+        Self::sign_at(keys, Utc::now().timestamp(), kind, tags, content)
+    }
+
+    fn sign_at(
+        keys: &Keypair,
+        created_at: i64,
+        kind: u32,
+        tags: Vec<Vec<String>>,
+        content: String,
+    ) -> Event {
+        let pubkey = hex::encode(keys.x_only_public_key().0.serialize());
+        let digest = event_digest(&pubkey, created_at, kind, &tags, &content);
+        let sig = SECP256K1.sign_schnorr_no_aux_rand(&Message::from_digest(digest), keys);
+
+        Event {
+            id: hex::encode(digest),
+            pubkey,
+            created_at,
+            kind,
+            tags,
+            content,
+            sig: hex::encode(sig.serialize()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Event {
+    /// Whether the id and signature match the rest of the event.
+    pub fn is_valid(&self) -> bool {
+        let digest = event_digest(
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
+        );
+        let signature = hex::decode(&self.sig)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok());
+
+        match (parse_public_key(&self.pubkey), signature) {
+            (Some(key), Some(signature)) => {
+                self.id == hex::encode(digest)
+                    && SECP256K1
+                        .verify_schnorr(&signature, &Message::from_digest(digest), &key)
+                        .is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+fn event_digest(
+    pubkey: &str,
+    created_at: i64,
+    kind: u32,
+    tags: &[Vec<String>],
+    content: &str,
+) -> [u8; 32] {
+    let serialized = json!([0, pubkey, created_at, kind, tags, content]).to_string();
+    Sha256::digest(serialized.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// NIP-19's example key pair.
+    const NPUB_EXAMPLE: &str = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+    const HEX_EXAMPLE: &str = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+    const NSEC_EXAMPLE: &str = "nsec1vl029mgpspedva04g90vltkh6fvh240zqtv9k0t9af8935ke9laqsnlfe5";
+    const SECRET_HEX_EXAMPLE: &str =
+        "67dea2ed018072d675f5415ecfaed7d2597555e202d85b3d65ea4e58d2d92ffa";
+
+    fn keys(secret: u8) -> Keypair {
+        let mut bytes = [0; 32];
+        bytes[31] = secret;
+        Keypair::from_seckey_slice(SECP256K1, &bytes).unwrap()
+    }
+
+    #[test]
+    fn bip340_test_vectors() {
+        // Vector 0: all zero auxiliary randomness, which is what signing
+        // without it uses.
+        let keys =
+            parse_secret_key("0000000000000000000000000000000000000000000000000000000000000003")
+                .unwrap();
+        assert_eq!(
+            keys.x_only_public_key().0,
+            parse_public_key("f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9")
+                .unwrap()
+        );
+        let signature = SECP256K1.sign_schnorr_no_aux_rand(&Message::from_digest([0; 32]), &keys);
+        assert_eq!(
+            hex::encode(signature.serialize()),
+            "e907831f80848d1069a5371b402410364bdf1c5f8307b0084c55f1ce2dca8215\
+             25f66a4a85ea8b71e482a74f382d2ce5ebeee8fdb2172f477df4900d310536c0"
+        );
+
+        // Vector 1, checked the way publisher signatures are.
+        let key =
+            parse_public_key("dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659")
+                .unwrap();
+        let message =
+            hex::decode("243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89")
+                .unwrap();
+        let signature = Signature::from_slice(
+            &hex::decode(
+                "6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de3341\
+                 8906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let message = Message::from_digest(message.try_into().unwrap());
+        assert!(SECP256K1.verify_schnorr(&signature, &message, &key).is_ok());
+    }
+
+    #[test]
+    fn public_keys_parse_from_npub_and_hex() {
+        let key = parse_public_key(NPUB_EXAMPLE).unwrap();
+
+        assert_eq!(hex::encode(key.serialize()), HEX_EXAMPLE);
+        assert_eq!(parse_public_key(HEX_EXAMPLE), Some(key));
+        assert_eq!(npub(&key), NPUB_EXAMPLE);
+    }
+
+    #[test]
+    fn bad_public_keys_are_rejected() {
+        // Wrong prefix, broken checksum, too short and not hex.
+        assert_eq!(parse_public_key(NSEC_EXAMPLE), None);
+        assert_eq!(
+            parse_public_key(&NPUB_EXAMPLE.replace("zvjptg", "zvjpth")),
+            None
+        );
+        assert_eq!(parse_public_key(&HEX_EXAMPLE[2..]), None);
+        assert_eq!(parse_public_key(&HEX_EXAMPLE.replace('7', "g")), None);
+    }
+
+    #[test]
+    fn secret_keys_parse_from_nsec_and_hex() {
+        let from_nsec = parse_secret_key(NSEC_EXAMPLE).unwrap();
+        let from_hex = parse_secret_key(SECRET_HEX_EXAMPLE).unwrap();
+
+        assert_eq!(from_nsec.secret_bytes(), from_hex.secret_bytes());
+        assert_eq!(npub(&from_nsec.x_only_public_key().0), NPUB_EXAMPLE);
+        assert!(parse_secret_key(NPUB_EXAMPLE).is_none());
+    }
+
+    #[test]
+    fn event_ids_follow_nip01() {
+        let event = Event::sign_at(
+            &keys(1),
+            1_700_000_000,
+            31990,
+            vec![
+                vec!["d".to_owned(), "summarize-text".to_owned()],
+                vec!["k".to_owned(), "5050".to_owned()],
+            ],
+            "{\"name\":\"summarize-text\"}\nline \"two\"".to_owned(),
+        );
+
+        // SHA-256 of
+        // [0,"79be...1798",1700000000,31990,[["d","summarize-text"],["k","5050"]],"{\"name\":...}\nline \"two\""]
+        assert_eq!(
+            event.id,
+            "2c31095877789dddcad1aa9d6dc7587a430db60d4e7ca507389469516e3bbf26"
+        );
+        assert_eq!(
+            event.pubkey,
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+        );
+        assert!(event.is_valid());
+
+        let mut tampered = event.clone();
+        tampered.content.push('!');
+        assert!(!tampered.is_valid());
+    }
+
+    #[test]
+    fn manifests_are_checked_against_the_module_digest() {
+        let keys = keys(7);
+        let manifest = Manifest {
+            name: "summarize-text",
+            version: "1.0.0",
+            sha256: &"ab".repeat(32),
+        };
+        assert_eq!(
+            manifest.serialize(),
+            format!(
+                r#"["openagents-plugin","summarize-text","1.0.0","{}"]"#,
+                "ab".repeat(32)
+            )
+        );
+        let signature = hex::encode(
+            SECP256K1
+                .sign_schnorr_no_aux_rand(&Message::from_digest(manifest.digest()), &keys)
+                .serialize(),
+        );
+        let public_key = hex::encode(keys.x_only_public_key().0.serialize());
+
+        assert_eq!(
+            manifest.verify(&public_key, &signature).unwrap(),
+            npub(&keys.x_only_public_key().0)
+        );
+
+        let other_module = Manifest {
+            sha256: &"cd".repeat(32),
+            ..manifest
+        };
+        let err = other_module.verify(&public_key, &signature).unwrap_err();
+        assert!(
+            matches!(&err, RegistryError::Validation(fields) if fields[0].field == "signature")
+        );
+        assert!(!other_module.is_signed_by(&public_key, &signature));
+    }
+}
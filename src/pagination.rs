@@ -0,0 +1,357 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder};
+use url::form_urlencoded;
+
+use crate::config::Config;
+use crate::error::RegistryError;
+use crate::plugin::{empty_string_as_none, Plugin};
+use crate::search::PluginMatch;
+
+/// Upper bound on `?limit=`, whatever the configured default page size.
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Orders plugins can be listed in. Each one breaks ties on the id so every
+/// row has a unique position for a cursor to point at.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    #[default]
+    Newest,
+    Name,
+    Downloads,
+    /// Best matches first, the order of every search.
+    Relevance,
+}
+
+impl Sort {
+    pub fn column(self) -> &'static str {
+        match self {
+            Self::Newest => "created_at",
+            Self::Name => "name",
+            Self::Downloads => "downloads",
+            Self::Relevance => "ts_rank(plugins.search, query)",
+        }
+    }
+
+    /// Postgres type the cursor value is cast to before comparing.
+    pub fn column_type(self) -> &'static str {
+        match self {
+            Self::Newest => "timestamptz",
+            Self::Name => "text",
+            Self::Downloads => "bigint",
+            Self::Relevance => "real",
+        }
+    }
+
+    pub fn descending(self) -> bool {
+        !matches!(self, Self::Name)
+    }
+
+    /// The plugin's value of the sort column. Relevance depends on the
+    /// search rather than the plugin, see [`Cursor::after_match`].
+    fn key(self, plugin: &Plugin) -> Option<String> {
+        match self {
+            Self::Newest => Some(plugin.created_at.to_rfc3339()),
+            Self::Name => Some(plugin.name.clone()),
+            Self::Downloads => Some(plugin.downloads.to_string()),
+            Self::Relevance => None,
+        }
+    }
+}
+
+impl fmt::Display for Sort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Newest => "newest",
+            Self::Name => "name",
+            Self::Downloads => "downloads",
+            Self::Relevance => "relevance",
+        })
+    }
+}
+
+/// Position of the last plugin on a page. Clients get it hex encoded and
+/// hand it back unchanged to fetch the next page.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cursor {
+    pub sort: Sort,
+    pub key: String,
+    pub id: i32,
+}
+
+impl Cursor {
+    pub fn after(sort: Sort, plugin: &Plugin) -> Option<Self> {
+        Some(Self {
+            sort,
+            key: sort.key(plugin)?,
+            id: plugin.id,
+        })
+    }
+
+    pub fn after_match(found: &PluginMatch) -> Self {
+        Self {
+            sort: Sort::Relevance,
+            key: found.rank.to_string(),
+            id: found.plugin.id,
+        }
+    }
+
+    /// Appends the condition for rows after the cursor to a query with a
+    /// `WHERE` clause.
+    pub fn push_after<'a>(&'a self, query: &mut QueryBuilder<'a, Postgres>) {
+        query
+            .push(format_args!(
+                " AND ({}, id) {} (",
+                self.sort.column(),
+                if self.sort.descending() { "<" } else { ">" }
+            ))
+            .push_bind(&self.key)
+            .push(format_args!("::{}, ", self.sort.column_type()))
+            .push_bind(self.id)
+            .push(")");
+    }
+
+    pub fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).expect("cursors always serialize"))
+    }
+
+    /// Cursors only make sense for the order they were created in.
+    pub fn decode(cursor: &str, sort: Sort) -> Result<Self, RegistryError> {
+        let invalid = || RegistryError::validation("cursor", "is not a valid cursor");
+
+        let bytes = hex::decode(cursor).map_err(|_| invalid())?;
+        let cursor: Cursor = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+        if cursor.sort != sort {
+            return Err(RegistryError::validation(
+                "cursor",
+                format!("was created for sort={}, not sort={}", cursor.sort, sort),
+            ));
+        }
+
+        Ok(cursor)
+    }
+}
+
+/// Conditions narrowing down which plugins are listed.
+#[derive(Default)]
+pub struct Filters<'a> {
+    pub query: &'a str,
+    pub tag: Option<&'a str>,
+    pub category: Option<&'a str>,
+}
+
+impl Filters<'_> {
+    /// Appends a `WHERE` clause for the filters. It is always present, so
+    /// callers can add their own conditions with `AND`.
+    pub fn push_where<'a>(&'a self, query: &mut QueryBuilder<'a, Postgres>) {
+        query.push(" WHERE TRUE");
+        if !self.query.is_empty() {
+            query
+                .push(" AND plugins.search @@ websearch_to_tsquery('english', ")
+                .push_bind(self.query)
+                .push(")");
+        }
+        if let Some(tag) = self.tag {
+            query
+                .push(
+                    " AND EXISTS (SELECT 1 FROM plugin_tags JOIN tags ON tags.id = plugin_tags.tag_id
+                     WHERE plugin_tags.plugin_id = plugins.id AND tags.name = ",
+                )
+                .push_bind(tag)
+                .push(")");
+        }
+        if let Some(category) = self.category {
+            query.push(" AND plugins.category = ").push_bind(category);
+        }
+    }
+}
+
+/// `?q=`, `?tag=`, `?category=`, `?sort=`, `?cursor=` and `?limit=` on plugin
+/// listings.
+#[derive(Default, Deserialize)]
+pub struct ListParams {
+    #[serde(default)]
+    pub q: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub tag: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub category: Option<String>,
+    /// Ignored by searches, which are always ordered by relevance.
+    #[serde(default)]
+    pub sort: Sort,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl ListParams {
+    pub fn query(&self) -> &str {
+        self.q.trim()
+    }
+
+    pub fn filters(&self) -> Filters<'_> {
+        Filters {
+            query: self.query(),
+            tag: self.tag.as_deref(),
+            category: self.category.as_deref(),
+        }
+    }
+
+    /// The order of the listing.
+    pub fn sort(&self) -> Result<Sort, RegistryError> {
+        match (self.query().is_empty(), self.sort) {
+            (false, _) => Ok(Sort::Relevance),
+            (true, Sort::Relevance) => Err(RegistryError::validation(
+                "sort",
+                "can only be relevance when searching with q",
+            )),
+            (true, sort) => Ok(sort),
+        }
+    }
+
+    pub fn cursor(&self) -> Result<Option<Cursor>, RegistryError> {
+        let sort = self.sort()?;
+
+        self.cursor
+            .as_deref()
+            .map(|cursor| Cursor::decode(cursor, sort))
+            .transpose()
+    }
+
+    pub fn limit(&self, config: &Config) -> usize {
+        self.limit
+            .unwrap_or(config.page_size)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// Query string for the page after `next_cursor`, keeping the filters.
+    pub fn next(&self, config: &Config, next_cursor: &str) -> String {
+        self.query_string(self.tag.as_deref(), self.category.as_deref())
+            .append_pair("limit", &self.limit(config).to_string())
+            .append_pair("cursor", next_cursor)
+            .finish()
+    }
+
+    /// Query string for the first page of this listing with the tag filter
+    /// set to `tag`, or removed when `tag` is the one already applied.
+    pub fn toggle_tag(&self, tag: &str) -> String {
+        let tag = (self.tag.as_deref() != Some(tag)).then_some(tag);
+        self.query_string(tag, self.category.as_deref()).finish()
+    }
+
+    /// Like [`ListParams::toggle_tag`], for the category filter.
+    pub fn toggle_category(&self, category: &str) -> String {
+        let category = (self.category.as_deref() != Some(category)).then_some(category);
+        self.query_string(self.tag.as_deref(), category).finish()
+    }
+
+    fn query_string(
+        &self,
+        tag: Option<&str>,
+        category: Option<&str>,
+    ) -> form_urlencoded::Serializer<'static, String> {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        if !self.query().is_empty() {
+            query.append_pair("q", self.query());
+        }
+        if let Some(tag) = tag {
+            query.append_pair("tag", tag);
+        }
+        if let Some(category) = category {
+            query.append_pair("category", category);
+        }
+        query.append_pair("sort", &self.sort.to_string());
+        query
+    }
+}
+
+pub struct Page {
+    pub plugins: Vec<Plugin>,
+    pub next_cursor: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(sort: Sort) -> String {
+        Cursor {
+            sort,
+            key: "summarize-text".to_owned(),
+            id: 7,
+        }
+        .encode()
+    }
+
+    fn listing(q: &str, sort: Sort, cursor: &str) -> ListParams {
+        ListParams {
+            q: q.to_owned(),
+            sort,
+            cursor: Some(cursor.to_owned()),
+            ..ListParams::default()
+        }
+    }
+
+    fn invalid_field(result: Result<Option<Cursor>, RegistryError>) -> String {
+        match result {
+            Err(RegistryError::Validation(fields)) => fields[0].field.clone(),
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(_) => panic!("cursor was accepted"),
+        }
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let decoded = Cursor::decode(&cursor(Sort::Name), Sort::Name).unwrap();
+
+        assert_eq!(decoded.sort, Sort::Name);
+        assert_eq!(decoded.key, "summarize-text");
+        assert_eq!(decoded.id, 7);
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        let not_json = hex::encode(b"not json");
+        let wrong_shape = hex::encode(br#"{"sort":"name","id":7}"#);
+
+        for malformed in ["zz", "abc", not_json.as_str(), wrong_shape.as_str()] {
+            assert_eq!(
+                invalid_field(listing("", Sort::Name, malformed).cursor()),
+                "cursor",
+                "{}",
+                malformed
+            );
+        }
+    }
+
+    #[test]
+    fn cursors_only_work_with_their_sort() {
+        let err = Cursor::decode(&cursor(Sort::Name), Sort::Newest).unwrap_err();
+        assert_eq!(
+            err.message(),
+            "cursor: was created for sort=name, not sort=newest"
+        );
+
+        // Searches are ordered by relevance whatever the sort asked for.
+        let search_cursor = cursor(Sort::Relevance);
+        assert!(listing("text", Sort::Name, &search_cursor).cursor().is_ok());
+        assert_eq!(
+            invalid_field(listing("", Sort::Newest, &search_cursor).cursor()),
+            "cursor"
+        );
+        assert_eq!(
+            invalid_field(listing("text", Sort::Newest, &cursor(Sort::Newest)).cursor()),
+            "cursor"
+        );
+    }
+
+    #[test]
+    fn relevance_needs_a_search() {
+        assert_eq!(
+            invalid_field(listing("", Sort::Relevance, &cursor(Sort::Relevance)).cursor()),
+            "sort"
+        );
+    }
+}
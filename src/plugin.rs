@@ -0,0 +1,465 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::types::Json;
+use sqlx::{PgExecutor, PgPool, Postgres, QueryBuilder};
+
+use crate::artifact::Artifact;
+use crate::error::RegistryError;
+use crate::events;
+use crate::nostr::Manifest;
+use crate::pagination::{Cursor, Filters, Page, Sort};
+use crate::taxonomy::{optional_tag_list, set_tags, tag_list};
+use crate::MutationKind;
+
+/// Select list for loading plugins along with their tags and owner.
+pub const PLUGIN_COLUMNS: &str = "plugins.*, ARRAY(
+    SELECT tags.name FROM plugin_tags JOIN tags ON tags.id = plugin_tags.tag_id
+    WHERE plugin_tags.plugin_id = plugins.id ORDER BY tags.name
+) AS tags, (SELECT username FROM users WHERE users.id = plugins.owner_id) AS owner";
+
+#[derive(sqlx::FromRow, Serialize, Deserialize)]
+pub struct Plugin {
+    pub id: i32,
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub wasm_url: String,
+    /// SHA-256 digest of the current version's module.
+    pub sha256: Option<String>,
+    pub author: Option<String>,
+    pub license: Option<String>,
+    pub homepage_url: Option<String>,
+    pub repository_url: Option<String>,
+    /// Slug of one of the curated [`crate::taxonomy::CATEGORIES`].
+    pub category: Option<String>,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The user who published the plugin, `None` for plugins published
+    /// before accounts existed.
+    pub owner_id: Option<i32>,
+    /// Username of the owner.
+    #[sqlx(default)]
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Nostr public key of the publisher, who signs each release's
+    /// [`Manifest`].
+    pub npub: Option<String>,
+    /// How many times the plugin's stored artifacts have been downloaded.
+    pub downloads: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Plugin {
+    /// One page of plugins matching `filters` in `sort` order, starting
+    /// after `cursor`.
+    pub async fn page(
+        db: impl PgExecutor<'_>,
+        filters: &Filters<'_>,
+        sort: Sort,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<Page, RegistryError> {
+        let direction = if sort.descending() { "DESC" } else { "ASC" };
+        let mut query = QueryBuilder::<Postgres>::new("SELECT ");
+        query.push(PLUGIN_COLUMNS).push(" FROM plugins");
+        filters.push_where(&mut query);
+
+        if let Some(cursor) = cursor {
+            cursor.push_after(&mut query);
+        }
+
+        // Fetch one extra row to find out whether there is another page.
+        query
+            .push(format_args!(
+                " ORDER BY {} {}, id {} LIMIT ",
+                sort.column(),
+                direction,
+                direction
+            ))
+            .push_bind(limit as i64 + 1);
+
+        let mut plugins = query.build_query_as::<Plugin>().fetch_all(db).await?;
+
+        let next_cursor = if plugins.len() > limit {
+            plugins.truncate(limit);
+            plugins
+                .last()
+                .and_then(|plugin| Cursor::after(sort, plugin))
+                .map(|cursor| cursor.encode())
+        } else {
+            None
+        };
+
+        Ok(Page {
+            plugins,
+            next_cursor,
+        })
+    }
+
+    pub async fn find(db: impl PgExecutor<'_>, id: i32) -> Result<Plugin, RegistryError> {
+        sqlx::query_as::<_, Plugin>(&format!(
+            "SELECT {} FROM plugins WHERE id = $1",
+            PLUGIN_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| RegistryError::plugin_not_found(id))
+    }
+
+    pub async fn create(
+        db: &PgPool,
+        owner_id: i32,
+        new: PluginNew,
+        artifact: &Artifact,
+    ) -> Result<Plugin, RegistryError> {
+        parse_version(&new.version)?;
+
+        let mut tx = db.begin().await?;
+        let (id,) = sqlx::query_as::<_, (i32,)>(
+            "WITH plugin AS (
+                INSERT INTO PLUGINS (name, version, description, wasm_url, author, license, homepage_url, repository_url, sha256, category, owner_id, npub)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $11, $12, $13)
+                RETURNING *
+             ), release AS (
+                INSERT INTO plugin_versions (plugin_id, version, wasm_url, sha256, module_info, signature)
+                SELECT id, version, wasm_url, sha256, $10, $14 FROM plugin
+             )
+             SELECT id FROM plugin",
+        )
+        .bind(&new.name)
+        .bind(new.version)
+        .bind(new.description)
+        .bind(new.wasm_url)
+        .bind(new.author)
+        .bind(new.license)
+        .bind(new.homepage_url)
+        .bind(new.repository_url)
+        .bind(&artifact.sha256)
+        .bind(Json(&artifact.module_info))
+        .bind(new.category)
+        .bind(owner_id)
+        .bind(new.npub)
+        .bind(new.signature)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|err| name_taken(err, &new.name))?;
+
+        set_tags(&mut tx, id, &new.tags).await?;
+        events::notify(&mut tx, MutationKind::Create, id).await?;
+        let plugin = Plugin::find(&mut *tx, id).await?;
+        tx.commit().await?;
+
+        Ok(plugin)
+    }
+
+    /// Overwrites every field of the plugin. Optional fields missing from
+    /// `new` are cleared. The release matching the new version is created or
+    /// pointed at the new WASM url.
+    pub async fn replace(
+        db: &PgPool,
+        id: i32,
+        new: PluginNew,
+        artifact: &Artifact,
+    ) -> Result<Plugin, RegistryError> {
+        parse_version(&new.version)?;
+
+        let mut tx = db.begin().await?;
+        sqlx::query(
+            "WITH plugin AS (
+                UPDATE PLUGINS SET name = $1, version = $2, description = $3, wasm_url = $4, author = $5,
+                    license = $6, homepage_url = $7, repository_url = $8, sha256 = $10, category = $12,
+                    npub = $13, updated_at = now()
+                WHERE ID = $9
+                RETURNING *
+             ), release AS (
+                INSERT INTO plugin_versions (plugin_id, version, wasm_url, sha256, module_info, signature)
+                SELECT id, version, wasm_url, sha256, $11, $14 FROM plugin
+                ON CONFLICT (plugin_id, version)
+                DO UPDATE SET wasm_url = EXCLUDED.wasm_url, sha256 = EXCLUDED.sha256,
+                    module_info = EXCLUDED.module_info, signature = EXCLUDED.signature
+             )
+             SELECT id FROM plugin",
+        )
+        .bind(&new.name)
+        .bind(new.version)
+        .bind(new.description)
+        .bind(new.wasm_url)
+        .bind(new.author)
+        .bind(new.license)
+        .bind(new.homepage_url)
+        .bind(new.repository_url)
+        .bind(id)
+        .bind(&artifact.sha256)
+        .bind(Json(&artifact.module_info))
+        .bind(new.category)
+        .bind(new.npub)
+        .bind(new.signature)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|err| name_taken(err, &new.name))?
+        .ok_or_else(|| RegistryError::plugin_not_found(id))?;
+
+        set_tags(&mut tx, id, &new.tags).await?;
+        events::notify(&mut tx, MutationKind::Update, id).await?;
+        let plugin = Plugin::find(&mut *tx, id).await?;
+        tx.commit().await?;
+
+        Ok(plugin)
+    }
+
+    /// Like [`Plugin::replace`], the release matching the resulting version
+    /// is kept in sync with the plugin's WASM url. `artifact` must be given
+    /// whenever the patch changes the version or WASM url. A release whose
+    /// module changes loses its signature, which no longer matches.
+    pub async fn update(
+        db: &PgPool,
+        id: i32,
+        patch: PluginPatch,
+        artifact: Option<&Artifact>,
+    ) -> Result<Plugin, RegistryError> {
+        if let Some(version) = &patch.version {
+            parse_version(version)?;
+        }
+
+        let cleared = patch.cleared();
+        let mut tx = db.begin().await?;
+        sqlx::query(
+            "WITH plugin AS (
+                UPDATE PLUGINS SET version = COALESCE($1, version), description = COALESCE($2, description),
+                    wasm_url = COALESCE($3, wasm_url),
+                    author = CASE WHEN 'author' = ANY($12) THEN NULL ELSE COALESCE($4, author) END,
+                    license = CASE WHEN 'license' = ANY($12) THEN NULL ELSE COALESCE($5, license) END,
+                    homepage_url = CASE WHEN 'homepage_url' = ANY($12)
+                        THEN NULL ELSE COALESCE($6, homepage_url) END,
+                    repository_url = CASE WHEN 'repository_url' = ANY($12)
+                        THEN NULL ELSE COALESCE($7, repository_url) END,
+                    sha256 = COALESCE($9, sha256),
+                    category = CASE WHEN 'category' = ANY($12) THEN NULL ELSE COALESCE($11, category) END,
+                    updated_at = now()
+                WHERE ID = $8
+                RETURNING *
+             ), release AS (
+                INSERT INTO plugin_versions (plugin_id, version, wasm_url, sha256, module_info)
+                SELECT id, version, wasm_url, sha256, $10 FROM plugin
+                ON CONFLICT (plugin_id, version)
+                DO UPDATE SET wasm_url = EXCLUDED.wasm_url, sha256 = EXCLUDED.sha256,
+                    module_info = COALESCE(EXCLUDED.module_info, plugin_versions.module_info),
+                    signature = CASE WHEN plugin_versions.sha256 = EXCLUDED.sha256
+                        THEN plugin_versions.signature END
+             )
+             SELECT id FROM plugin",
+        )
+        .bind(patch.version)
+        .bind(patch.description)
+        .bind(patch.wasm_url)
+        .bind(patch.author.flatten())
+        .bind(patch.license.flatten())
+        .bind(patch.homepage_url.flatten())
+        .bind(patch.repository_url.flatten())
+        .bind(id)
+        .bind(artifact.map(|artifact| &artifact.sha256))
+        .bind(artifact.map(|artifact| Json(&artifact.module_info)))
+        .bind(patch.category.flatten())
+        .bind(cleared)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| RegistryError::plugin_not_found(id))?;
+
+        if let Some(tags) = &patch.tags {
+            set_tags(&mut tx, id, tags).await?;
+        }
+        events::notify(&mut tx, MutationKind::Update, id).await?;
+        let plugin = Plugin::find(&mut *tx, id).await?;
+        tx.commit().await?;
+
+        Ok(plugin)
+    }
+
+    /// Counts a download of an artifact against every plugin that published
+    /// it.
+    pub async fn record_download(
+        db: impl PgExecutor<'_>,
+        sha256: &str,
+    ) -> Result<(), RegistryError> {
+        sqlx::query(
+            "UPDATE plugins SET downloads = downloads + 1
+             WHERE id IN (SELECT plugin_id FROM plugin_versions WHERE sha256 = $1)",
+        )
+        .bind(sha256)
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(db: &PgPool, id: i32) -> Result<(), RegistryError> {
+        let mut tx = db.begin().await?;
+        sqlx::query("SELECT id FROM plugins WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| RegistryError::plugin_not_found(id))?;
+
+        // Logged first so webhooks get the plugin as it was.
+        events::notify(&mut tx, MutationKind::Delete, id).await?;
+        sqlx::query("DELETE FROM PLUGINS WHERE ID = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct PluginNew {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub wasm_url: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub author: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub license: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub homepage_url: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub repository_url: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub category: Option<String>,
+    #[serde(default, deserialize_with = "tag_list")]
+    pub tags: Vec<String>,
+    /// Nostr public key of the publisher, as `npub1...` or hex.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub npub: Option<String>,
+    /// The publisher's hex encoded Schnorr signature of the release's
+    /// [`Manifest`], required along with `npub`.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub signature: Option<String>,
+}
+
+impl PluginNew {
+    /// Checks the publisher's signature against the ingested module, and
+    /// stores their key as an npub.
+    pub fn verify_signature(&mut self, artifact: &Artifact) -> Result<(), RegistryError> {
+        if let (Some(npub), Some(signature)) = (&self.npub, &self.signature) {
+            let manifest = Manifest {
+                name: &self.name,
+                version: &self.version,
+                sha256: &artifact.sha256,
+            };
+            self.npub = Some(manifest.verify(npub, signature)?);
+        }
+
+        Ok(())
+    }
+}
+
+/// Partial update of a plugin; `None` fields are left untouched. Optional
+/// metadata is cleared by sending it empty or `null`, which deserializes to
+/// `Some(None)`. The name is the plugin's stable identifier and can only be
+/// changed by a full replace.
+#[derive(Deserialize)]
+pub struct PluginPatch {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub version: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub description: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub wasm_url: Option<String>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub author: Option<Option<String>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub license: Option<Option<String>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub homepage_url: Option<Option<String>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub repository_url: Option<Option<String>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub category: Option<Option<String>>,
+    /// Replaces all of the plugin's tags when present.
+    #[serde(default, deserialize_with = "optional_tag_list")]
+    pub tags: Option<Vec<String>>,
+}
+
+impl PluginPatch {
+    /// Columns of the optional metadata the patch clears.
+    fn cleared(&self) -> Vec<&'static str> {
+        [
+            ("author", &self.author),
+            ("license", &self.license),
+            ("homepage_url", &self.homepage_url),
+            ("repository_url", &self.repository_url),
+            ("category", &self.category),
+        ]
+        .into_iter()
+        .filter(|(_, value)| matches!(value, Some(None)))
+        .map(|(column, _)| column)
+        .collect()
+    }
+}
+
+/// HTML forms submit blank inputs as empty strings; treat those as missing.
+pub fn empty_string_as_none<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty()))
+}
+
+/// Like [`empty_string_as_none`], for patch fields that can be cleared: a
+/// field that is present but blank or `null` becomes `Some(None)`. Missing
+/// fields are left to `#[serde(default)]`.
+fn clearable<'de, D>(deserializer: D) -> Result<Option<Option<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    empty_string_as_none(deserializer).map(Some)
+}
+
+pub fn parse_version(version: &str) -> Result<semver::Version, RegistryError> {
+    semver::Version::parse(version).map_err(|err| {
+        RegistryError::validation("version", format!("is not a semantic version: {}", err))
+    })
+}
+
+fn name_taken(err: sqlx::Error, name: &str) -> RegistryError {
+    match RegistryError::from(err) {
+        RegistryError::Conflict(_) => {
+            RegistryError::Conflict(format!("a plugin named {:?} already exists", name))
+        }
+        err => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_tells_missing_from_cleared_fields() {
+        let patch: PluginPatch = serde_json::from_value(serde_json::json!({
+            "author": " ",
+            "license": null,
+            "homepage_url": "https://example.com",
+        }))
+        .unwrap();
+
+        assert_eq!(patch.author, Some(None));
+        assert_eq!(patch.license, Some(None));
+        assert_eq!(
+            patch.homepage_url,
+            Some(Some("https://example.com".to_owned()))
+        );
+        assert_eq!(patch.repository_url, None);
+        assert_eq!(patch.category, None);
+        assert_eq!(patch.cleared(), ["author", "license"]);
+    }
+}
@@ -0,0 +1,766 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::Bytes,
+    extract::{
+        rejection::{BytesRejection, PathRejection},
+        Path, State,
+    },
+    http::StatusCode,
+    Json,
+};
+use serde::Serialize;
+use wasmtime::{
+    Caller, Engine, Error, ExternType, Linker, Module, ResourceLimiter, Store, Trap, Val, ValType,
+};
+
+use crate::config::Config;
+use crate::error::{JsonError, RegistryError};
+use crate::plugin::Plugin;
+use crate::AppState;
+
+/// Import module of the host functions plugins use to exchange data with
+/// the registry, following the Extism ABI.
+const EXTISM_ENV: &str = "extism:host/env";
+/// How often the engine's epoch advances, i.e. the granularity of the
+/// wall-clock limit.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+/// Compiled modules kept for repeated calls, keyed by digest.
+const MODULE_CACHE_SIZE: usize = 32;
+const TABLE_MAX_ELEMENTS: usize = 10_000;
+
+/// Runs exported plugin functions in a sandbox. Plugins get the Extism host
+/// functions for input, output, logging and variables and nothing else: no
+/// WASI, no network and no filesystem. Any other import traps when called.
+#[derive(Clone)]
+pub struct Runtime {
+    engine: Engine,
+    linker: Arc<Linker<Host>>,
+    modules: Arc<Mutex<HashMap<String, Module>>>,
+    fuel_limit: u64,
+    memory_limit: usize,
+    timeout: Duration,
+}
+
+#[derive(Serialize)]
+pub struct LogLine {
+    pub level: &'static str,
+    pub message: String,
+}
+
+/// What a plugin call produced. The call ran even when `error` is set: the
+/// plugin trapped, ran out of one of its limits or reported an error.
+#[derive(Serialize)]
+pub struct CallOutcome {
+    pub output: String,
+    /// `utf8`, or `hex` when the output is not valid UTF-8.
+    pub output_encoding: &'static str,
+    pub error: Option<String>,
+    /// Value returned by functions with an `i32` result, where anything but
+    /// zero means failure.
+    pub return_code: Option<i32>,
+    pub logs: Vec<LogLine>,
+    pub fuel_used: u64,
+    pub duration_ms: f64,
+    /// Peak linear memory plus host memory used by the call.
+    pub memory_bytes: usize,
+}
+
+impl Runtime {
+    pub fn new(config: &Config) -> Self {
+        let mut engine_config = wasmtime::Config::new();
+        engine_config.consume_fuel(true).epoch_interruption(true);
+        let engine =
+            Engine::new(&engine_config).expect("Looks like the WASM engine could not be built :(");
+
+        let ticker = engine.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(EPOCH_TICK);
+            ticker.increment_epoch();
+        });
+
+        let mut linker = Linker::new(&engine);
+        define_host_functions(&mut linker)
+            .expect("Looks like the host functions could not be defined :(");
+
+        Self {
+            engine,
+            linker: Arc::new(linker),
+            modules: Arc::default(),
+            fuel_limit: config.run_fuel_limit,
+            memory_limit: config.run_memory_limit,
+            timeout: Duration::from_millis(config.run_timeout_ms),
+        }
+    }
+
+    /// Calls `function` with `input`. Compiling and running happen on the
+    /// blocking pool since guest code never yields. `digest` identifies the
+    /// module for the compile cache; modules without one are not cached.
+    pub async fn call(
+        &self,
+        digest: Option<String>,
+        bytes: Vec<u8>,
+        function: String,
+        input: Vec<u8>,
+    ) -> Result<CallOutcome, RegistryError> {
+        let runtime = self.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let module = runtime.module(digest, &bytes)?;
+            runtime.run(&module, &function, &input)
+        })
+        .await
+        .map_err(|err| RegistryError::Internal(format!("plugin call failed: {}", err)))?
+    }
+
+    fn module(&self, digest: Option<String>, bytes: &[u8]) -> Result<Module, RegistryError> {
+        if let Some(module) = digest
+            .as_ref()
+            .and_then(|digest| self.modules.lock().unwrap().get(digest).cloned())
+        {
+            return Ok(module);
+        }
+
+        let module = Module::new(&self.engine, bytes).map_err(|err| {
+            RegistryError::BadRequest(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("the module could not be compiled: {}", err),
+            )
+        })?;
+
+        if let Some(digest) = digest {
+            let mut modules = self.modules.lock().unwrap();
+            if modules.len() >= MODULE_CACHE_SIZE {
+                modules.clear();
+            }
+            modules.insert(digest, module.clone());
+        }
+
+        Ok(module)
+    }
+
+    fn run(
+        &self,
+        module: &Module,
+        function: &str,
+        input: &[u8],
+    ) -> Result<CallOutcome, RegistryError> {
+        let results = match module.get_export(function) {
+            Some(ExternType::Func(ty)) => {
+                let results: Vec<ValType> = ty.results().collect();
+                if ty.params().len() > 0
+                    || results.len() > 1
+                    || results
+                        .first()
+                        .is_some_and(|ty| !matches!(ty, ValType::I32))
+                {
+                    return Err(RegistryError::validation(
+                        "function",
+                        "must take no parameters and return nothing or an i32",
+                    ));
+                }
+                results.len()
+            }
+            _ => {
+                return Err(RegistryError::NotFound(format!(
+                    "the module exports no function named {}",
+                    function
+                )))
+            }
+        };
+
+        let mut linker = (*self.linker).clone();
+        linker
+            .define_unknown_imports_as_traps(module)
+            .map_err(|err| {
+                RegistryError::BadRequest(StatusCode::UNPROCESSABLE_ENTITY, err.to_string())
+            })?;
+
+        let mut store = Store::new(&self.engine, Host::new(self.memory_limit));
+        store.limiter(|host| host);
+        store.set_fuel(self.fuel_limit).expect("fuel is enabled");
+        store.set_epoch_deadline(self.timeout.as_millis().div_ceil(EPOCH_TICK.as_millis()) as u64);
+        store.epoch_deadline_trap();
+
+        let start = Instant::now();
+        let returned = store
+            .data_mut()
+            .write_input(input)
+            .and_then(|()| linker.instantiate(&mut store, module))
+            .and_then(|instance| {
+                let func = instance
+                    .get_func(&mut store, function)
+                    .expect("checked the export above");
+                let mut values = vec![Val::I32(0); results];
+                func.call(&mut store, &[], &mut values)?;
+                Ok(values.first().and_then(Val::i32))
+            });
+        let duration = start.elapsed();
+
+        let fuel_used = self.fuel_limit - store.get_fuel().unwrap_or(0);
+        let host = store.into_data();
+        let return_code = returned.as_ref().ok().copied().flatten();
+        let error = match returned {
+            Ok(Some(code)) if code != 0 => {
+                Some(host.error.unwrap_or_else(|| format!("returned {}", code)))
+            }
+            Ok(_) => None,
+            Err(err) => Some(self.describe_failure(err)),
+        };
+        let (output, output_encoding) = match String::from_utf8(host.output) {
+            Ok(output) => (output, "utf8"),
+            Err(err) => (hex::encode(err.into_bytes()), "hex"),
+        };
+
+        Ok(CallOutcome {
+            output,
+            output_encoding,
+            error,
+            return_code,
+            logs: host.logs,
+            fuel_used,
+            duration_ms: duration.as_secs_f64() * 1000.0,
+            memory_bytes: host.peak_linear_memory + host.memory.bytes.len(),
+        })
+    }
+
+    fn describe_failure(&self, err: Error) -> String {
+        match err.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel) => format!("ran out of fuel after {} units", self.fuel_limit),
+            Some(Trap::Interrupt) => {
+                format!("timed out after {}ms", self.timeout.as_millis())
+            }
+            _ => err.root_cause().to_string(),
+        }
+    }
+}
+
+/// Per-call state: the host side memory Extism plugins exchange data
+/// through, and what the plugin logged and returned.
+struct Host {
+    memory_limit: usize,
+    peak_linear_memory: usize,
+    memory: HostMemory,
+    input: (u64, u64),
+    output: Vec<u8>,
+    error: Option<String>,
+    logs: Vec<LogLine>,
+    vars: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Host {
+    fn new(memory_limit: usize) -> Self {
+        Self {
+            memory_limit,
+            peak_linear_memory: 0,
+            memory: HostMemory::default(),
+            input: (0, 0),
+            output: Vec::new(),
+            error: None,
+            logs: Vec::new(),
+            vars: HashMap::new(),
+        }
+    }
+
+    fn write_input(&mut self, input: &[u8]) -> Result<(), Error> {
+        let offset = self.write(input)?;
+        self.input = (offset, input.len() as u64);
+        Ok(())
+    }
+
+    /// Host memory counts towards the same limit as linear memory.
+    fn alloc(&mut self, len: u64) -> Result<u64, Error> {
+        let used = usize::try_from(len).ok().and_then(|len| {
+            self.peak_linear_memory
+                .checked_add(self.memory.bytes.len())?
+                .checked_add(len)
+        });
+        if used.is_none_or(|used| used > self.memory_limit) {
+            return Err(self.out_of_memory());
+        }
+        Ok(self.memory.alloc(len))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<u64, Error> {
+        let offset = self.alloc(bytes.len() as u64)?;
+        self.memory
+            .slice_mut(offset, bytes.len() as u64)?
+            .copy_from_slice(bytes);
+        Ok(offset)
+    }
+
+    fn input_slice(&self, offset: u64, len: u64) -> Result<&[u8], Error> {
+        let (start, input_len) = self.input;
+        if offset.saturating_add(len) > input_len {
+            return Err(Error::msg("input read out of bounds"));
+        }
+        self.memory.slice(start + offset, len)
+    }
+
+    fn log(&mut self, level: &'static str, offset: u64) -> Result<(), Error> {
+        let message = String::from_utf8_lossy(self.memory.block(offset)?).into_owned();
+        self.logs.push(LogLine { level, message });
+        Ok(())
+    }
+
+    fn out_of_memory(&self) -> Error {
+        Error::msg(format!(
+            "memory limit of {} bytes exceeded",
+            self.memory_limit
+        ))
+    }
+}
+
+impl ResourceLimiter for Host {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool, Error> {
+        if desired.saturating_add(self.memory.bytes.len()) > self.memory_limit {
+            return Err(self.out_of_memory());
+        }
+        self.peak_linear_memory = self.peak_linear_memory.max(desired);
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool, Error> {
+        Ok(desired <= TABLE_MAX_ELEMENTS)
+    }
+}
+
+/// Bump allocated memory addressed by the offsets `alloc` hands out. Offset
+/// 0 is reserved so plugins can use it as null.
+struct HostMemory {
+    bytes: Vec<u8>,
+    blocks: HashMap<u64, u64>,
+}
+
+impl Default for HostMemory {
+    fn default() -> Self {
+        Self {
+            bytes: vec![0],
+            blocks: HashMap::new(),
+        }
+    }
+}
+
+impl HostMemory {
+    fn alloc(&mut self, len: u64) -> u64 {
+        let offset = self.bytes.len() as u64;
+        self.bytes.resize(self.bytes.len() + len as usize, 0);
+        self.blocks.insert(offset, len);
+        offset
+    }
+
+    fn free(&mut self, offset: u64) {
+        self.blocks.remove(&offset);
+    }
+
+    fn length(&self, offset: u64) -> u64 {
+        self.blocks.get(&offset).copied().unwrap_or(0)
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn block(&self, offset: u64) -> Result<&[u8], Error> {
+        self.slice(offset, self.length(offset))
+    }
+
+    fn slice(&self, offset: u64, len: u64) -> Result<&[u8], Error> {
+        let range = Self::range(offset, len, self.bytes.len())?;
+        Ok(&self.bytes[range])
+    }
+
+    fn slice_mut(&mut self, offset: u64, len: u64) -> Result<&mut [u8], Error> {
+        let range = Self::range(offset, len, self.bytes.len())?;
+        Ok(&mut self.bytes[range])
+    }
+
+    fn range(offset: u64, len: u64, size: usize) -> Result<std::ops::Range<usize>, Error> {
+        let end = offset.saturating_add(len);
+        if offset == 0 || end > size as u64 {
+            return Err(Error::msg(format!(
+                "host memory access out of bounds at offset {}",
+                offset
+            )));
+        }
+        Ok(offset as usize..end as usize)
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> i64 {
+    i64::from_le_bytes(bytes.try_into().expect("read exactly 8 bytes"))
+}
+
+/// The subset of the Extism kernel plugins need to run in a sandbox.
+/// Pointers and lengths are `i64` offsets into [`HostMemory`].
+fn define_host_functions(linker: &mut Linker<Host>) -> Result<(), Error> {
+    linker.func_wrap(
+        EXTISM_ENV,
+        "alloc",
+        |mut caller: Caller<'_, Host>, len: i64| {
+            let len = u64::try_from(len)
+                .map_err(|_| Error::msg(format!("cannot allocate {} bytes", len)))?;
+            caller.data_mut().alloc(len).map(|offset| offset as i64)
+        },
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "free",
+        |mut caller: Caller<'_, Host>, offset: i64| caller.data_mut().memory.free(offset as u64),
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "length",
+        |caller: Caller<'_, Host>, offset: i64| caller.data().memory.length(offset as u64) as i64,
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "length_unsafe",
+        |caller: Caller<'_, Host>, offset: i64| caller.data().memory.length(offset as u64) as i64,
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "load_u8",
+        |caller: Caller<'_, Host>, offset: i64| {
+            caller
+                .data()
+                .memory
+                .slice(offset as u64, 1)
+                .map(|bytes| bytes[0] as i32)
+        },
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "load_u64",
+        |caller: Caller<'_, Host>, offset: i64| {
+            caller.data().memory.slice(offset as u64, 8).map(read_u64)
+        },
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "store_u8",
+        |mut caller: Caller<'_, Host>, offset: i64, value: i32| {
+            caller.data_mut().memory.slice_mut(offset as u64, 1)?[0] = value as u8;
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "store_u64",
+        |mut caller: Caller<'_, Host>, offset: i64, value: i64| {
+            caller
+                .data_mut()
+                .memory
+                .slice_mut(offset as u64, 8)?
+                .copy_from_slice(&value.to_le_bytes());
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(EXTISM_ENV, "input_offset", |caller: Caller<'_, Host>| {
+        caller.data().input.0 as i64
+    })?;
+    linker.func_wrap(EXTISM_ENV, "input_length", |caller: Caller<'_, Host>| {
+        caller.data().input.1 as i64
+    })?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "input_load_u8",
+        |caller: Caller<'_, Host>, offset: i64| {
+            caller
+                .data()
+                .input_slice(offset as u64, 1)
+                .map(|bytes| bytes[0] as i32)
+        },
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "input_load_u64",
+        |caller: Caller<'_, Host>, offset: i64| {
+            caller.data().input_slice(offset as u64, 8).map(read_u64)
+        },
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "output_set",
+        |mut caller: Caller<'_, Host>, offset: i64, len: i64| {
+            let host = caller.data_mut();
+            host.output = host.memory.slice(offset as u64, len as u64)?.to_vec();
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "error_set",
+        |mut caller: Caller<'_, Host>, offset: i64| {
+            let host = caller.data_mut();
+            host.error = match offset {
+                0 => None,
+                offset => {
+                    Some(String::from_utf8_lossy(host.memory.block(offset as u64)?).into_owned())
+                }
+            };
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "error_get",
+        |mut caller: Caller<'_, Host>| match caller.data().error.clone() {
+            Some(error) => caller
+                .data_mut()
+                .write(error.as_bytes())
+                .map(|offset| offset as i64),
+            None => Ok(0),
+        },
+    )?;
+    linker.func_wrap(EXTISM_ENV, "reset", |mut caller: Caller<'_, Host>| {
+        let host = caller.data_mut();
+        host.memory.reset();
+        host.input = (0, 0);
+    })?;
+
+    // Plugins run without configuration, so every key is missing.
+    linker.func_wrap(
+        EXTISM_ENV,
+        "config_get",
+        |_: Caller<'_, Host>, _key: i64| 0i64,
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "var_get",
+        |mut caller: Caller<'_, Host>, key: i64| {
+            let host = caller.data_mut();
+            let value = host.vars.get(host.memory.block(key as u64)?).cloned();
+            match value {
+                Some(value) => host.write(&value).map(|offset| offset as i64),
+                None => Ok(0),
+            }
+        },
+    )?;
+    linker.func_wrap(
+        EXTISM_ENV,
+        "var_set",
+        |mut caller: Caller<'_, Host>, key: i64, value: i64| {
+            let host = caller.data_mut();
+            let key = host.memory.block(key as u64)?.to_vec();
+            match value {
+                0 => host.vars.remove(&key),
+                value => {
+                    let value = host.memory.block(value as u64)?.to_vec();
+                    host.vars.insert(key, value)
+                }
+            };
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        EXTISM_ENV,
+        "http_request",
+        |_: Caller<'_, Host>, _request: i64, _body: i64| -> Result<i64, Error> {
+            Err(Error::msg("network access is disabled in the sandbox"))
+        },
+    )?;
+    linker.func_wrap(EXTISM_ENV, "http_status_code", |_: Caller<'_, Host>| 0i32)?;
+
+    for (name, level) in [
+        ("log_trace", "trace"),
+        ("log_debug", "debug"),
+        ("log_info", "info"),
+        ("log_warn", "warn"),
+        ("log_error", "error"),
+    ] {
+        linker.func_wrap(
+            EXTISM_ENV,
+            name,
+            move |mut caller: Caller<'_, Host>, offset: i64| {
+                caller.data_mut().log(level, offset as u64)
+            },
+        )?;
+    }
+    // Everything is captured, so plugins should log at every level.
+    linker.func_wrap(EXTISM_ENV, "get_log_level", |_: Caller<'_, Host>| 0i32)?;
+
+    Ok(())
+}
+
+/// Runs an exported function of the plugin's current module.
+pub async fn run_plugin(
+    state: &AppState,
+    id: i32,
+    function: String,
+    input: Vec<u8>,
+) -> Result<CallOutcome, RegistryError> {
+    let plugin = Plugin::find(&state.db, id).await?;
+    let bytes = state
+        .artifacts
+        .load(plugin.sha256.as_deref(), &plugin.wasm_url)
+        .await?;
+
+    state
+        .runtime
+        .call(plugin.sha256, bytes, function, input)
+        .await
+}
+
+/// `POST /plugins/:id/call/:function`, with the request body as input.
+pub async fn call_plugin(
+    State(state): State<AppState>,
+    params: Result<Path<(i32, String)>, PathRejection>,
+    input: Result<Bytes, BytesRejection>,
+) -> Result<Json<CallOutcome>, JsonError> {
+    let Path((id, function)) = params?;
+    let input = input?;
+
+    let outcome = run_plugin(&state, id, function, input.to_vec()).await?;
+
+    Ok(Json(outcome))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runtime(fuel: u64, memory: usize, timeout_ms: u64) -> Runtime {
+        Runtime::new(&Config {
+            run_fuel_limit: fuel,
+            run_memory_limit: memory,
+            run_timeout_ms: timeout_ms,
+            ..Config::default()
+        })
+    }
+
+    async fn call(runtime: &Runtime, wat: &str, input: &[u8]) -> CallOutcome {
+        let bytes = wat::parse_str(wat).unwrap();
+        runtime
+            .call(None, bytes, "run".to_owned(), input.to_vec())
+            .await
+            .unwrap()
+    }
+
+    const ALLOC: &str =
+        r#"(import "extism:host/env" "alloc" (func $alloc (param i64) (result i64)))"#;
+
+    #[tokio::test]
+    async fn echoes_input() {
+        let outcome = call(
+            &runtime(1_000_000, 1 << 20, 1_000),
+            r#"(module
+                (import "extism:host/env" "input_offset" (func $offset (result i64)))
+                (import "extism:host/env" "input_length" (func $length (result i64)))
+                (import "extism:host/env" "output_set" (func $output (param i64 i64)))
+                (func (export "run") (result i32)
+                    (call $output (call $offset) (call $length))
+                    (i32.const 0)))"#,
+            b"hello",
+        )
+        .await;
+
+        assert_eq!(outcome.error, None);
+        assert_eq!(outcome.output, "hello");
+        assert_eq!(outcome.return_code, Some(0));
+    }
+
+    #[tokio::test]
+    async fn endless_loops_run_out_of_fuel() {
+        let outcome = call(
+            &runtime(10_000, 1 << 20, 60_000),
+            r#"(module (func (export "run") (loop (br 0))))"#,
+            b"",
+        )
+        .await;
+
+        assert_eq!(
+            outcome.error.as_deref(),
+            Some("ran out of fuel after 10000 units")
+        );
+        assert_eq!(outcome.fuel_used, 10_000);
+    }
+
+    #[tokio::test]
+    async fn endless_loops_time_out() {
+        let outcome = call(
+            &runtime(u64::MAX, 1 << 20, 50),
+            r#"(module (func (export "run") (loop (br 0))))"#,
+            b"",
+        )
+        .await;
+
+        assert_eq!(outcome.error.as_deref(), Some("timed out after 50ms"));
+    }
+
+    #[tokio::test]
+    async fn linear_memory_is_limited() {
+        let outcome = call(
+            &runtime(1_000_000, 1 << 20, 1_000),
+            r#"(module
+                (memory 1)
+                (func (export "run") (result i32)
+                    (drop (memory.grow (i32.const 100)))
+                    (i32.const 0)))"#,
+            b"",
+        )
+        .await;
+
+        assert_eq!(
+            outcome.error.as_deref(),
+            Some("memory limit of 1048576 bytes exceeded")
+        );
+    }
+
+    #[tokio::test]
+    async fn host_memory_is_limited() {
+        for (len, expected) in [
+            ("-1", "cannot allocate -1 bytes"),
+            (
+                "9223372036854775807",
+                "memory limit of 1048576 bytes exceeded",
+            ),
+            ("2097152", "memory limit of 1048576 bytes exceeded"),
+        ] {
+            let outcome = call(
+                &runtime(1_000_000, 1 << 20, 1_000),
+                &format!(
+                    r#"(module {}
+                        (func (export "run") (drop (call $alloc (i64.const {})))))"#,
+                    ALLOC, len
+                ),
+                b"",
+            )
+            .await;
+
+            assert_eq!(outcome.error.as_deref(), Some(expected), "{}", len);
+            assert!(outcome.memory_bytes <= 1 << 20);
+        }
+    }
+
+    #[tokio::test]
+    async fn host_memory_is_checked_on_access() {
+        let outcome = call(
+            &runtime(1_000_000, 1 << 20, 1_000),
+            r#"(module
+                (import "extism:host/env" "output_set" (func $output (param i64 i64)))
+                (func (export "run") (call $output (i64.const 1) (i64.const -1))))"#,
+            b"",
+        )
+        .await;
+
+        assert_eq!(
+            outcome.error.as_deref(),
+            Some("host memory access out of bounds at offset 1")
+        );
+    }
+}
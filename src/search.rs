@@ -0,0 +1,117 @@
+use serde::Serialize;
+use sqlx::{PgExecutor, Postgres, QueryBuilder};
+
+use crate::error::RegistryError;
+use crate::pagination::{Cursor, Filters};
+use crate::plugin::{Plugin, PLUGIN_COLUMNS};
+
+/// Markers Postgres puts around matched words in a headline. Control
+/// characters can't clash with anything a publisher types, and keep the
+/// headline plain text until the template escapes it.
+const HIGHLIGHT_START: char = '\u{2}';
+const HIGHLIGHT_STOP: char = '\u{3}';
+
+/// A plugin matching a search, best matches first.
+#[derive(sqlx::FromRow, Serialize)]
+pub struct PluginMatch {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub plugin: Plugin,
+    pub rank: f32,
+    #[sqlx(try_from = "String")]
+    pub snippet: Snippet,
+}
+
+/// Excerpt of a plugin's description with the words that matched the query
+/// highlighted.
+#[derive(Serialize)]
+pub struct Snippet(pub Vec<SnippetPart>);
+
+#[derive(Serialize)]
+pub struct SnippetPart {
+    pub text: String,
+    pub highlighted: bool,
+}
+
+impl From<String> for Snippet {
+    fn from(headline: String) -> Self {
+        let mut parts = Vec::new();
+        let mut push = |text: &str, highlighted| {
+            if !text.is_empty() {
+                parts.push(SnippetPart {
+                    text: text.to_owned(),
+                    highlighted,
+                });
+            }
+        };
+
+        let mut pieces = headline.split(HIGHLIGHT_START);
+        push(pieces.next().unwrap_or_default(), false);
+        for piece in pieces {
+            let (highlighted, rest) = piece.split_once(HIGHLIGHT_STOP).unwrap_or((piece, ""));
+            push(highlighted, true);
+            push(rest, false);
+        }
+
+        Self(parts)
+    }
+}
+
+/// One page of search results.
+pub struct SearchPage {
+    pub matches: Vec<PluginMatch>,
+    pub next_cursor: Option<String>,
+}
+
+impl PluginMatch {
+    /// Full text search over plugin names, tags and descriptions, returning
+    /// a page of `limit` matches after `cursor`, best first. The query uses
+    /// web search syntax: quoted phrases, `or` and `-excluded` words.
+    pub async fn search(
+        db: impl PgExecutor<'_>,
+        filters: &Filters<'_>,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<SearchPage, RegistryError> {
+        let headline_options = format!(
+            "StartSel={}, StopSel={}, MaxFragments=2, MaxWords=20, MinWords=8",
+            HIGHLIGHT_START, HIGHLIGHT_STOP
+        );
+
+        let mut query = QueryBuilder::<Postgres>::new("SELECT ");
+        query
+            .push(PLUGIN_COLUMNS)
+            .push(
+                ", ts_rank(search, query) AS rank,
+                ts_headline('english', description, query, ",
+            )
+            .push_bind(headline_options)
+            .push(") AS snippet FROM plugins, websearch_to_tsquery('english', ")
+            .push_bind(filters.query)
+            .push(") query");
+        filters.push_where(&mut query);
+        if let Some(cursor) = cursor {
+            cursor.push_after(&mut query);
+        }
+        // Fetch one extra row to find out whether there is another page.
+        query
+            .push(" ORDER BY rank DESC, id DESC LIMIT ")
+            .push_bind(limit as i64 + 1);
+
+        let mut matches = query.build_query_as::<PluginMatch>().fetch_all(db).await?;
+
+        let next_cursor = if matches.len() > limit {
+            matches.truncate(limit);
+            matches
+                .last()
+                .map(|found| Cursor::after_match(found).encode())
+        } else {
+            None
+        };
+
+        Ok(SearchPage {
+            matches,
+            next_cursor,
+        })
+    }
+}
@@ -0,0 +1,221 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::{PgExecutor, PgPool, Postgres, QueryBuilder};
+
+use crate::error::RegistryError;
+use crate::pagination::Filters;
+
+/// A curated category. Unlike tags, publishers can only pick from this list.
+pub struct Category {
+    pub slug: &'static str,
+    pub name: &'static str,
+}
+
+pub const CATEGORIES: &[Category] = &[
+    Category {
+        slug: "summarization",
+        name: "Summarization",
+    },
+    Category {
+        slug: "search",
+        name: "Search",
+    },
+    Category {
+        slug: "data-extraction",
+        name: "Data extraction",
+    },
+    Category {
+        slug: "translation",
+        name: "Translation",
+    },
+    Category {
+        slug: "code",
+        name: "Code",
+    },
+    Category {
+        slug: "media",
+        name: "Images and media",
+    },
+    Category {
+        slug: "productivity",
+        name: "Productivity",
+    },
+    Category {
+        slug: "finance",
+        name: "Finance",
+    },
+    Category {
+        slug: "developer-tools",
+        name: "Developer tools",
+    },
+    Category {
+        slug: "other",
+        name: "Other",
+    },
+];
+
+pub fn category(slug: &str) -> Option<&'static Category> {
+    CATEGORIES.iter().find(|category| category.slug == slug)
+}
+
+/// Display name of a category, falling back to the slug for categories that
+/// have since been removed from the list.
+pub fn category_name(slug: &str) -> &str {
+    category(slug).map_or(slug, |category| category.name)
+}
+
+/// Tags arrive as a comma separated string from forms and as a list from the
+/// API. Either way they are trimmed, lowercased and deduplicated.
+pub fn tag_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Tags {
+        Joined(String),
+        List(Vec<String>),
+    }
+
+    let tags = match Tags::deserialize(deserializer)? {
+        Tags::Joined(tags) => tags.split(',').map(str::to_owned).collect(),
+        Tags::List(tags) => tags,
+    };
+
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+
+    Ok(normalized)
+}
+
+/// Like [`tag_list`], for patches where a missing field leaves the tags as
+/// they are.
+pub fn optional_tag_list<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    tag_list(deserializer).map(Some)
+}
+
+/// Replaces the plugin's tags, creating any that don't exist yet.
+pub async fn set_tags(
+    db: &mut sqlx::PgConnection,
+    plugin_id: i32,
+    tags: &[String],
+) -> Result<(), RegistryError> {
+    sqlx::query("DELETE FROM plugin_tags WHERE plugin_id = $1")
+        .bind(plugin_id)
+        .execute(&mut *db)
+        .await?;
+
+    sqlx::query("INSERT INTO tags (name) SELECT unnest($1::text[]) ON CONFLICT (name) DO NOTHING")
+        .bind(tags)
+        .execute(&mut *db)
+        .await?;
+
+    sqlx::query(
+        "INSERT INTO plugin_tags (plugin_id, tag_id)
+         SELECT $1, id FROM tags WHERE name = ANY($2)",
+    )
+    .bind(plugin_id)
+    .bind(tags)
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+/// How many plugins matching the current filters fall under each value.
+#[derive(sqlx::FromRow, Serialize)]
+pub struct Facet {
+    pub value: String,
+    pub count: i64,
+}
+
+/// Only the most used tags are worth offering as filters.
+const TAG_FACET_LIMIT: i64 = 30;
+
+#[derive(Default, Serialize)]
+pub struct Facets {
+    pub categories: Vec<Facet>,
+    pub tags: Vec<Facet>,
+}
+
+impl Facets {
+    /// Each facet is counted under every filter but its own, so picking a
+    /// category still shows how many plugins the other categories have.
+    pub async fn load(db: &PgPool, filters: &Filters<'_>) -> Result<Facets, RegistryError> {
+        let category_filters = Filters {
+            category: None,
+            ..*filters
+        };
+        let mut query = QueryBuilder::<Postgres>::new(
+            "SELECT category AS value, count(*) AS count FROM plugins",
+        );
+        category_filters.push_where(&mut query);
+        query.push(" AND category IS NOT NULL GROUP BY category ORDER BY count DESC, category");
+        let categories = fetch_facets(db, query).await?;
+
+        let mut query = QueryBuilder::<Postgres>::new(
+            "SELECT tags.name AS value, count(*) AS count FROM plugins
+             JOIN plugin_tags ON plugin_tags.plugin_id = plugins.id
+             JOIN tags ON tags.id = plugin_tags.tag_id",
+        );
+        let tag_filters = Filters {
+            tag: None,
+            ..*filters
+        };
+        tag_filters.push_where(&mut query);
+        query
+            .push(" GROUP BY tags.name ORDER BY count DESC, tags.name LIMIT ")
+            .push_bind(TAG_FACET_LIMIT);
+        let tags = fetch_facets(db, query).await?;
+
+        Ok(Facets { categories, tags })
+    }
+}
+
+async fn fetch_facets(
+    db: impl PgExecutor<'_>,
+    mut query: QueryBuilder<'_, Postgres>,
+) -> Result<Vec<Facet>, RegistryError> {
+    Ok(query.build_query_as::<Facet>().fetch_all(db).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Deserialize)]
+    struct Tagged {
+        #[serde(deserialize_with = "tag_list")]
+        tags: Vec<String>,
+    }
+
+    fn tags(value: serde_json::Value) -> Vec<String> {
+        serde_json::from_value::<Tagged>(json!({ "tags": value }))
+            .unwrap()
+            .tags
+    }
+
+    #[test]
+    fn tags_are_normalized() {
+        assert_eq!(tags(json!("ai, Text,,  ,AI ,text")), ["ai", "text"]);
+        assert_eq!(
+            tags(json!(["Ai", "", " ai", "TEXT", "text "])),
+            ["ai", "text"]
+        );
+        assert!(tags(json!("")).is_empty());
+        assert!(tags(json!([])).is_empty());
+    }
+
+    #[test]
+    fn tag_order_is_kept() {
+        assert_eq!(tags(json!("zeta,alpha,Zeta")), ["zeta", "alpha"]);
+    }
+}
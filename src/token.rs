@@ -0,0 +1,200 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgExecutor;
+
+use crate::account::{token_hash, User};
+use crate::error::RegistryError;
+
+/// Personal API tokens start with this, which tells them apart from session
+/// tokens and makes them easy to spot in leaked config.
+pub const TOKEN_PREFIX: &str = "reg_";
+
+/// Longest lifetime a token can be created with; tokens without an expiry
+/// are allowed too.
+pub const MAX_TOKEN_DAYS: i64 = 365;
+
+/// What an API token may be used for. Logged in browser sessions can do
+/// everything.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Read what is private to the token's user: their account, webhooks
+    /// and webhook deliveries. Everything else can be read without a token.
+    Read,
+    /// Create plugins, change them and publish new versions.
+    Publish,
+    /// Delete plugins.
+    Yank,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::Read, Scope::Publish, Scope::Yank];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Publish => "publish",
+            Self::Yank => "yank",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = RegistryError;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|known| known.as_str() == scope)
+            .ok_or_else(|| {
+                RegistryError::validation("scopes", format!("{:?} is not a scope", scope))
+            })
+    }
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct ApiToken {
+    pub id: i32,
+    pub name: String,
+    /// The first characters of the token, for telling tokens apart.
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A token as returned right after creating it, the only time the secret
+/// itself is shown.
+#[derive(Serialize)]
+pub struct NewApiToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub details: ApiToken,
+}
+
+#[derive(Deserialize)]
+pub struct ApiTokenNew {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Days until the token expires; it never does when missing.
+    pub expires_in_days: Option<i64>,
+}
+
+/// User a token belongs to, along with what the token allows.
+#[derive(sqlx::FromRow)]
+struct TokenOwner {
+    #[sqlx(flatten)]
+    user: User,
+    scopes: Vec<String>,
+}
+
+impl ApiToken {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    pub async fn for_user(
+        db: impl PgExecutor<'_>,
+        user_id: i32,
+    ) -> Result<Vec<ApiToken>, RegistryError> {
+        let tokens = sqlx::query_as::<_, ApiToken>(
+            "SELECT * FROM api_tokens WHERE user_id = $1 ORDER BY created_at DESC, id DESC",
+        )
+        .bind(user_id)
+        .fetch_all(db)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    pub async fn create(
+        db: impl PgExecutor<'_>,
+        user_id: i32,
+        new: ApiTokenNew,
+    ) -> Result<NewApiToken, RegistryError> {
+        let token = format!(
+            "{}{}",
+            TOKEN_PREFIX,
+            hex::encode(rand::random::<[u8; 32]>())
+        );
+        let expires_at = new
+            .expires_in_days
+            .map(|days| Utc::now() + Duration::days(days));
+        let scopes: Vec<_> = new.scopes.iter().map(|scope| scope.as_str()).collect();
+
+        let details = sqlx::query_as::<_, ApiToken>(
+            "INSERT INTO api_tokens (user_id, name, token_hash, token_prefix, scopes, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING *",
+        )
+        .bind(user_id)
+        .bind(new.name)
+        .bind(token_hash(&token))
+        .bind(&token[..TOKEN_PREFIX.len() + 8])
+        .bind(scopes)
+        .bind(expires_at)
+        .fetch_one(db)
+        .await?;
+
+        Ok(NewApiToken { token, details })
+    }
+
+    /// Revokes one of the user's tokens.
+    pub async fn delete(
+        db: impl PgExecutor<'_>,
+        user_id: i32,
+        id: i32,
+    ) -> Result<(), RegistryError> {
+        let result = sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(db)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(RegistryError::NotFound(format!("token {} not found", id)));
+        }
+
+        Ok(())
+    }
+
+    /// The user a valid, unexpired token belongs to and the token's scopes.
+    /// Records when the token was last used.
+    pub async fn authenticate(
+        db: impl PgExecutor<'_>,
+        token: &str,
+    ) -> Result<Option<(User, Vec<Scope>)>, RegistryError> {
+        let owner = sqlx::query_as::<_, TokenOwner>(
+            "WITH token AS (
+                UPDATE api_tokens SET last_used_at = now()
+                WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > now())
+                RETURNING user_id, scopes
+             )
+             SELECT users.*, token.scopes FROM token JOIN users ON users.id = token.user_id",
+        )
+        .bind(token_hash(token))
+        .fetch_optional(db)
+        .await?;
+
+        Ok(owner.map(|owner| {
+            // Scopes that have since been removed are ignored.
+            let scopes = owner
+                .scopes
+                .iter()
+                .filter_map(|scope| scope.parse().ok())
+                .collect();
+            (owner.user, scopes)
+        }))
+    }
+}
@@ -0,0 +1,106 @@
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{FromRequest, Multipart},
+    http::{header, Request, StatusCode},
+    Form, Json,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::error::RegistryError;
+use crate::AppState;
+
+/// Name of the multipart field that carries an uploaded module.
+pub const WASM_FILE_FIELD: &str = "wasm_file";
+
+/// A `.wasm` file uploaded alongside a submission.
+pub struct WasmFile {
+    pub file_name: String,
+    /// `None` when the file was larger than `UPLOAD_MAX_SIZE`. The rest of
+    /// the file is skipped so the other fields can still be read.
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// A submission that can arrive urlencoded, as JSON or as
+/// `multipart/form-data` with an optional [`WASM_FILE_FIELD`] file next to
+/// the regular fields.
+pub struct WasmUpload<T> {
+    pub fields: T,
+    pub wasm_file: Option<WasmFile>,
+}
+
+#[async_trait]
+impl<T> FromRequest<AppState, Body> for WasmUpload<T>
+where
+    T: DeserializeOwned + Send,
+{
+    type Rejection = RegistryError;
+
+    async fn from_request(req: Request<Body>, state: &AppState) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        if content_type.starts_with("multipart/form-data") {
+            let multipart = Multipart::from_request(req, state).await?;
+            return read_multipart(multipart, state.config.upload_max_size).await;
+        }
+
+        let fields = if content_type.starts_with("application/json") {
+            Json::<T>::from_request(req, state).await?.0
+        } else {
+            Form::<T>::from_request(req, state).await?.0
+        };
+
+        Ok(Self {
+            fields,
+            wasm_file: None,
+        })
+    }
+}
+
+async fn read_multipart<T: DeserializeOwned>(
+    mut multipart: Multipart,
+    max_size: usize,
+) -> Result<WasmUpload<T>, RegistryError> {
+    let mut fields = Map::new();
+    let mut wasm_file = None;
+
+    while let Some(mut field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_owned();
+
+        if name != WASM_FILE_FIELD {
+            fields.insert(name, Value::String(field.text().await?));
+            continue;
+        }
+
+        let file_name = field.file_name().unwrap_or_default().to_owned();
+        let mut bytes = Some(Vec::new());
+        while let Some(chunk) = field.chunk().await? {
+            if let Some(buffer) = &mut bytes {
+                if buffer.len() + chunk.len() > max_size {
+                    bytes = None;
+                } else {
+                    buffer.extend_from_slice(&chunk);
+                }
+            }
+        }
+
+        // Browsers send an empty part when the file input is left blank.
+        if !file_name.is_empty() || bytes.as_ref().is_none_or(|bytes| !bytes.is_empty()) {
+            wasm_file = Some(WasmFile { file_name, bytes });
+        }
+    }
+
+    let fields = serde_json::from_value(Value::Object(fields)).map_err(|err| {
+        RegistryError::BadRequest(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Failed to deserialize form body: {}", err),
+        )
+    })?;
+
+    Ok(WasmUpload { fields, wasm_file })
+}
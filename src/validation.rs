@@ -0,0 +1,536 @@
+use url::Url;
+
+use crate::account::Credentials;
+use crate::config::Config;
+use crate::error::{FieldError, RegistryError};
+use crate::nostr;
+use crate::plugin::{PluginNew, PluginPatch};
+use crate::taxonomy::{self, CATEGORIES};
+use crate::token::{ApiTokenNew, MAX_TOKEN_DAYS};
+use crate::version::PluginVersionNew;
+use crate::wasm;
+use crate::webhook::WebhookNew;
+
+/// Field errors handed to the add form template so each input can show its
+/// own message.
+#[derive(Default)]
+pub struct FieldErrors(pub Vec<FieldError>);
+
+impl FieldErrors {
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|error| error.field == field)
+            .map(|error| error.message.as_str())
+    }
+}
+
+const NAME_MAX_LENGTH: usize = 64;
+const AUTHOR_MAX_LENGTH: usize = 100;
+const TAG_MAX_LENGTH: usize = 32;
+const USERNAME_MAX_LENGTH: usize = 32;
+const PASSWORD_MIN_LENGTH: usize = 8;
+const PASSWORD_MAX_LENGTH: usize = 256;
+const TOKEN_NAME_MAX_LENGTH: usize = 64;
+const MAX_TAGS: usize = 10;
+
+/// Collects every problem with a submission so the form can show them all at
+/// once instead of one per round trip.
+struct Validator<'a> {
+    config: &'a Config,
+    errors: Vec<FieldError>,
+}
+
+impl<'a> Validator<'a> {
+    fn new(config: &'a Config) -> Self {
+        Self {
+            config,
+            errors: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, field: &str, message: impl Into<String>) {
+        self.errors.push(FieldError::new(field, message));
+    }
+
+    /// Names are used in urls and by agents to refer to a plugin, so they
+    /// are restricted to lowercase slugs like `summarize-text`.
+    fn name(&mut self, name: &str) {
+        if name.is_empty() || name.len() > NAME_MAX_LENGTH {
+            return self.error(
+                "name",
+                format!("must be between 1 and {} characters", NAME_MAX_LENGTH),
+            );
+        }
+
+        if !name.starts_with(|c: char| c.is_ascii_lowercase()) {
+            return self.error("name", "must start with a lowercase letter");
+        }
+
+        if !is_slug(name) {
+            self.error(
+                "name",
+                "may only contain lowercase letters, digits and single dashes",
+            );
+        }
+    }
+
+    /// Tags are filtered on through urls, so they follow the same rules as
+    /// names.
+    fn tags(&mut self, tags: &[String]) {
+        if tags.len() > MAX_TAGS {
+            return self.error("tags", format!("must be at most {} tags", MAX_TAGS));
+        }
+
+        for tag in tags {
+            if tag.len() > TAG_MAX_LENGTH || !is_slug(tag) {
+                return self.error(
+                    "tags",
+                    format!(
+                        "{:?} is not a tag: use up to {} lowercase letters, digits and single dashes",
+                        tag, TAG_MAX_LENGTH
+                    ),
+                );
+            }
+        }
+    }
+
+    fn category(&mut self, category: &str) {
+        if taxonomy::category(category).is_none() {
+            let slugs: Vec<_> = CATEGORIES.iter().map(|category| category.slug).collect();
+            self.error("category", format!("must be one of: {}", slugs.join(", ")));
+        }
+    }
+
+    fn version(&mut self, version: &str) {
+        if let Err(err) = semver::Version::parse(version) {
+            self.error("version", format!("is not a semantic version: {}", err));
+        }
+    }
+
+    fn description(&mut self, description: &str) {
+        let length = description.trim().chars().count();
+        let (min, max) = (
+            self.config.description_min_length,
+            self.config.description_max_length,
+        );
+
+        if length < min || length > max {
+            self.error(
+                "description",
+                format!("must be between {} and {} characters", min, max),
+            );
+        }
+    }
+
+    fn url(&mut self, field: &str, url: &str) {
+        let url = match Url::parse(url) {
+            Ok(url) => url,
+            Err(err) => return self.error(field, format!("is not a valid url: {}", err)),
+        };
+
+        if !self
+            .config
+            .allowed_url_schemes
+            .iter()
+            .any(|scheme| scheme == url.scheme())
+        {
+            return self.error(
+                field,
+                format!(
+                    "must use one of these schemes: {}",
+                    self.config.allowed_url_schemes.join(", ")
+                ),
+            );
+        }
+
+        if url.host_str().is_none_or(str::is_empty) {
+            self.error(field, "must include a host");
+        }
+    }
+
+    /// Artifacts served by the registry itself are trusted whatever scheme
+    /// the registry runs on.
+    fn wasm_url(&mut self, url: &str) {
+        if url.is_empty() {
+            return self.error("wasm_url", "is required unless a .wasm file is uploaded");
+        }
+
+        if self.config.artifact_digest(url).is_none() {
+            self.url("wasm_url", url);
+        }
+    }
+
+    fn author(&mut self, author: &str) {
+        if author.chars().count() > AUTHOR_MAX_LENGTH {
+            self.error(
+                "author",
+                format!("must be at most {} characters", AUTHOR_MAX_LENGTH),
+            );
+        }
+    }
+
+    fn license(&mut self, license: &str) {
+        if let Err(err) = spdx::Expression::parse(license) {
+            self.error(
+                "license",
+                format!("is not a valid SPDX license expression: {}", err.reason),
+            );
+        }
+    }
+
+    /// A publisher signs with the key they give, so both come together. The
+    /// signature itself is checked once the module's digest is known.
+    fn publisher(&mut self, npub: Option<&str>, signature: Option<&str>) {
+        match (npub, signature) {
+            (Some(npub), signature) => {
+                if nostr::parse_public_key(npub).is_none() {
+                    self.error("npub", "is not an npub or hex encoded public key");
+                }
+                match signature {
+                    Some(signature) => self.signature(signature),
+                    None => self.error("signature", "is required to publish with an npub"),
+                }
+            }
+            (None, Some(_)) => self.error("npub", "is required to check the signature"),
+            (None, None) => {}
+        }
+    }
+
+    fn signature(&mut self, signature: &str) {
+        if signature.len() != 128 || !signature.chars().all(|c| c.is_ascii_hexdigit()) {
+            self.error("signature", "must be 64 hex encoded bytes");
+        }
+    }
+
+    fn finish(self) -> Result<(), RegistryError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(RegistryError::Validation(self.errors))
+        }
+    }
+}
+
+fn is_slug(value: &str) -> bool {
+    let valid_chars = value
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    valid_chars && !value.starts_with('-') && !value.ends_with('-') && !value.contains("--")
+}
+
+impl PluginNew {
+    pub fn validate(&self, config: &Config) -> Result<(), RegistryError> {
+        let mut validator = Validator::new(config);
+
+        validator.name(&self.name);
+        validator.version(&self.version);
+        validator.description(&self.description);
+        validator.wasm_url(&self.wasm_url);
+        if let Some(author) = &self.author {
+            validator.author(author);
+        }
+        if let Some(license) = &self.license {
+            validator.license(license);
+        }
+        if let Some(homepage_url) = &self.homepage_url {
+            validator.url("homepage_url", homepage_url);
+        }
+        if let Some(repository_url) = &self.repository_url {
+            validator.url("repository_url", repository_url);
+        }
+        if let Some(category) = &self.category {
+            validator.category(category);
+        }
+        validator.tags(&self.tags);
+        validator.publisher(self.npub.as_deref(), self.signature.as_deref());
+
+        validator.finish()
+    }
+}
+
+impl PluginPatch {
+    pub fn validate(&self, config: &Config) -> Result<(), RegistryError> {
+        let mut validator = Validator::new(config);
+
+        if let Some(version) = &self.version {
+            validator.version(version);
+        }
+        if let Some(description) = &self.description {
+            validator.description(description);
+        }
+        if let Some(wasm_url) = &self.wasm_url {
+            validator.wasm_url(wasm_url);
+        }
+        if let Some(Some(author)) = &self.author {
+            validator.author(author);
+        }
+        if let Some(Some(license)) = &self.license {
+            validator.license(license);
+        }
+        if let Some(Some(homepage_url)) = &self.homepage_url {
+            validator.url("homepage_url", homepage_url);
+        }
+        if let Some(Some(repository_url)) = &self.repository_url {
+            validator.url("repository_url", repository_url);
+        }
+        if let Some(Some(category)) = &self.category {
+            validator.category(category);
+        }
+        if let Some(tags) = &self.tags {
+            validator.tags(tags);
+        }
+
+        validator.finish()
+    }
+}
+
+impl PluginVersionNew {
+    pub fn validate(&self, config: &Config) -> Result<(), RegistryError> {
+        let mut validator = Validator::new(config);
+
+        validator.version(&self.version);
+        validator.wasm_url(&self.wasm_url);
+        if let Some(signature) = &self.signature {
+            validator.signature(signature);
+        }
+
+        validator.finish()
+    }
+}
+
+impl Credentials {
+    /// Rules for new accounts. Logging in only checks the credentials match.
+    pub fn validate(&self, config: &Config) -> Result<(), RegistryError> {
+        let mut validator = Validator::new(config);
+
+        if self.username.is_empty() || self.username.len() > USERNAME_MAX_LENGTH {
+            validator.error(
+                "username",
+                format!("must be between 1 and {} characters", USERNAME_MAX_LENGTH),
+            );
+        } else if !is_slug(&self.username) {
+            validator.error(
+                "username",
+                "may only contain lowercase letters, digits and single dashes",
+            );
+        }
+
+        let length = self.password.chars().count();
+        if !(PASSWORD_MIN_LENGTH..=PASSWORD_MAX_LENGTH).contains(&length) {
+            validator.error(
+                "password",
+                format!(
+                    "must be between {} and {} characters",
+                    PASSWORD_MIN_LENGTH, PASSWORD_MAX_LENGTH
+                ),
+            );
+        }
+
+        validator.finish()
+    }
+}
+
+impl ApiTokenNew {
+    pub fn validate(&self, config: &Config) -> Result<(), RegistryError> {
+        let mut validator = Validator::new(config);
+
+        let length = self.name.trim().chars().count();
+        if length == 0 || length > TOKEN_NAME_MAX_LENGTH {
+            validator.error(
+                "name",
+                format!("must be between 1 and {} characters", TOKEN_NAME_MAX_LENGTH),
+            );
+        }
+        if self.scopes.is_empty() {
+            validator.error("scopes", "must include at least one scope");
+        }
+        if let Some(days) = self.expires_in_days {
+            if !(1..=MAX_TOKEN_DAYS).contains(&days) {
+                validator.error(
+                    "expires_in_days",
+                    format!("must be between 1 and {}", MAX_TOKEN_DAYS),
+                );
+            }
+        }
+
+        validator.finish()
+    }
+}
+
+impl WebhookNew {
+    pub fn validate(&self, config: &Config) -> Result<(), RegistryError> {
+        let mut validator = Validator::new(config);
+
+        validator.url("url", &self.url);
+        // Deliveries to names that resolve privately fail when they are sent.
+        if Url::parse(&self.url).is_ok_and(|url| wasm::has_private_ip(&url)) {
+            validator.error("url", "must not point at a private address");
+        }
+        if self.events.is_empty() {
+            validator.error(
+                "events",
+                "must include at least one of create, update and delete",
+            );
+        }
+        if let Some(tag) = &self.tag {
+            if tag.len() > TAG_MAX_LENGTH || !is_slug(tag) {
+                validator.error("tag", format!("{:?} is not a tag", tag));
+            }
+        }
+        if let Some(owner) = &self.owner {
+            if owner.len() > USERNAME_MAX_LENGTH || !is_slug(owner) {
+                validator.error("owner", format!("{:?} is not a username", owner));
+            }
+        }
+
+        validator.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// x-only public key of the secret key 1.
+    const PUBLIC_KEY: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    fn errors(check: impl FnOnce(&mut Validator)) -> Vec<String> {
+        let config = Config {
+            description_min_length: 5,
+            description_max_length: 10,
+            ..Config::default()
+        };
+        let mut validator = Validator::new(&config);
+        check(&mut validator);
+
+        validator
+            .errors
+            .into_iter()
+            .map(|error| error.field)
+            .collect()
+    }
+
+    #[test]
+    fn slugs() {
+        for slug in ["a", "summarize-text", "v2", "2-fast"] {
+            assert!(is_slug(slug), "{:?}", slug);
+        }
+        for not_slug in ["-a", "a-", "a--b", "Upper", "snake_case", "spa ce", "ünï"] {
+            assert!(!is_slug(not_slug), "{:?}", not_slug);
+        }
+    }
+
+    #[test]
+    fn names_start_with_a_letter() {
+        assert!(errors(|v| v.name("summarize-text")).is_empty());
+        assert_eq!(errors(|v| v.name("")), ["name"]);
+        assert_eq!(errors(|v| v.name("2-fast")), ["name"]);
+        assert_eq!(errors(|v| v.name("a--b")), ["name"]);
+        assert_eq!(
+            errors(|v| v.name(&"a".repeat(NAME_MAX_LENGTH + 1))),
+            ["name"]
+        );
+    }
+
+    #[test]
+    fn description_length_is_configured() {
+        assert!(errors(|v| v.description("12345")).is_empty());
+        assert!(errors(|v| v.description("1234567890")).is_empty());
+        assert!(errors(|v| v.description("  12345  ")).is_empty());
+        assert_eq!(errors(|v| v.description("1234")), ["description"]);
+        assert_eq!(errors(|v| v.description("12345678901")), ["description"]);
+        // Counted in characters, not bytes.
+        assert!(errors(|v| v.description("ééééé")).is_empty());
+    }
+
+    #[test]
+    fn urls_need_an_allowed_scheme_and_a_host() {
+        assert!(errors(|v| v.url("homepage_url", "https://example.com/a")).is_empty());
+        assert_eq!(
+            errors(|v| v.url("homepage_url", "http://example.com")),
+            ["homepage_url"]
+        );
+        assert_eq!(
+            errors(|v| v.url("homepage_url", "ftp://example.com")),
+            ["homepage_url"]
+        );
+        assert_eq!(
+            errors(|v| v.url("homepage_url", "https://")),
+            ["homepage_url"]
+        );
+        assert_eq!(
+            errors(|v| v.url("homepage_url", "not a url")),
+            ["homepage_url"]
+        );
+    }
+
+    #[test]
+    fn registry_artifacts_skip_the_scheme_check() {
+        let digest = "ab".repeat(32);
+        // The default public url is plain http.
+        let artifact_url = Config::default().artifact_url(&digest);
+
+        assert!(errors(|v| v.wasm_url(&artifact_url)).is_empty());
+        assert!(errors(|v| v.wasm_url("https://example.com/plugin.wasm")).is_empty());
+        assert_eq!(
+            errors(|v| v.wasm_url("http://example.com/plugin.wasm")),
+            ["wasm_url"]
+        );
+        assert_eq!(errors(|v| v.wasm_url("")), ["wasm_url"]);
+    }
+
+    #[test]
+    fn npub_and_signature_come_together() {
+        let signature = "00".repeat(64);
+
+        assert!(errors(|v| v.publisher(None, None)).is_empty());
+        assert!(errors(|v| v.publisher(Some(PUBLIC_KEY), Some(&signature))).is_empty());
+        assert_eq!(
+            errors(|v| v.publisher(Some(PUBLIC_KEY), None)),
+            ["signature"]
+        );
+        assert_eq!(errors(|v| v.publisher(None, Some(&signature))), ["npub"]);
+        assert_eq!(
+            errors(|v| v.publisher(Some("npub1nope"), Some("beef"))),
+            ["npub", "signature"]
+        );
+    }
+
+    #[test]
+    fn webhooks_must_not_point_at_private_addresses() {
+        let config = Config {
+            allowed_url_schemes: vec!["http".to_owned(), "https".to_owned()],
+            ..Config::default()
+        };
+        let webhook = |url: &str| WebhookNew {
+            url: url.to_owned(),
+            events: vec![crate::MutationKind::Create],
+            plugin_id: None,
+            tag: None,
+            owner: None,
+        };
+
+        assert!(webhook("https://example.com/hooks")
+            .validate(&config)
+            .is_ok());
+        assert!(webhook("http://93.184.216.34/hooks")
+            .validate(&config)
+            .is_ok());
+        for url in [
+            "http://127.0.0.1:5432/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.0.0.1/hooks",
+            "http://[::1]/hooks",
+            "http://[::ffff:192.168.1.1]/hooks",
+        ] {
+            let err = webhook(url).validate(&config).unwrap_err();
+            assert!(
+                err.message().contains("private address"),
+                "{}: {:?}",
+                url,
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use chrono::{DateTime, Utc};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{PgExecutor, PgPool};
+
+use crate::artifact::Artifact;
+use crate::error::RegistryError;
+use crate::events;
+use crate::nostr::Manifest;
+use crate::plugin::{empty_string_as_none, parse_version, Plugin};
+use crate::wasm::ModuleInfo;
+use crate::MutationKind;
+
+/// A single release of a plugin, pointing at the WASM artifact for that
+/// version.
+#[derive(sqlx::FromRow, Serialize, Deserialize)]
+pub struct PluginVersion {
+    pub id: i32,
+    pub plugin_id: i32,
+    pub version: String,
+    pub wasm_url: String,
+    /// SHA-256 digest of the module, missing for releases published before
+    /// digests were recorded.
+    pub sha256: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Missing for releases published before modules were inspected.
+    pub module_info: Option<Json<ModuleInfo>>,
+    /// The publisher's Schnorr signature of the release's [`Manifest`].
+    pub signature: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PluginVersionNew {
+    pub version: String,
+    pub wasm_url: String,
+    /// Signature by the plugin's publisher, see [`PluginVersion::signature`].
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub signature: Option<String>,
+}
+
+impl PluginVersionNew {
+    /// Checks the signature, if any, against the ingested module and the
+    /// key the plugin was published with.
+    pub fn verify_signature(
+        &self,
+        plugin: &Plugin,
+        artifact: &Artifact,
+    ) -> Result<(), RegistryError> {
+        let Some(signature) = &self.signature else {
+            return Ok(());
+        };
+        let npub = plugin.npub.as_deref().ok_or_else(|| {
+            RegistryError::validation(
+                "signature",
+                "can't be checked as the plugin has no publisher npub",
+            )
+        })?;
+
+        let manifest = Manifest {
+            name: &plugin.name,
+            version: &self.version,
+            sha256: &artifact.sha256,
+        };
+        manifest.verify(npub, signature)?;
+
+        Ok(())
+    }
+}
+
+/// Whether a release is vouched for by the plugin's publisher, with what
+/// agents need to check that for themselves.
+#[derive(Serialize)]
+pub struct Verification {
+    pub npub: Option<String>,
+    /// Exact text the signature is made over, missing for releases without
+    /// a digest.
+    pub manifest: Option<String>,
+    pub signature: Option<String>,
+    pub verified: bool,
+}
+
+impl PluginVersion {
+    /// Versions are validated on the way in, so this only fails for rows
+    /// written by hand.
+    pub fn semver(&self) -> Option<Version> {
+        Version::parse(&self.version).ok()
+    }
+
+    /// Re-checks the release's signature against `plugin`'s publisher.
+    pub fn verification(&self, plugin: &Plugin) -> Verification {
+        let manifest = self.sha256.as_deref().map(|sha256| Manifest {
+            name: &plugin.name,
+            version: &self.version,
+            sha256,
+        });
+        let verified = match (&manifest, &plugin.npub, &self.signature) {
+            (Some(manifest), Some(npub), Some(signature)) => manifest.is_signed_by(npub, signature),
+            _ => false,
+        };
+
+        Verification {
+            npub: plugin.npub.clone(),
+            manifest: manifest.map(|manifest| manifest.serialize()),
+            signature: self.signature.clone(),
+            verified,
+        }
+    }
+
+    pub fn is_verified(&self, plugin: &Plugin) -> bool {
+        self.verification(plugin).verified
+    }
+
+    /// All releases of a plugin, highest version first.
+    pub async fn for_plugin(
+        db: impl PgExecutor<'_>,
+        plugin_id: i32,
+    ) -> Result<Vec<PluginVersion>, RegistryError> {
+        let mut versions = sqlx::query_as::<_, PluginVersion>(
+            "SELECT * FROM plugin_versions WHERE plugin_id = $1",
+        )
+        .bind(plugin_id)
+        .fetch_all(db)
+        .await?;
+
+        versions.sort_by_key(|version| std::cmp::Reverse(version.semver()));
+
+        Ok(versions)
+    }
+
+    pub async fn find(
+        db: impl PgExecutor<'_>,
+        plugin_id: i32,
+        version: &str,
+    ) -> Result<PluginVersion, RegistryError> {
+        sqlx::query_as::<_, PluginVersion>(
+            "SELECT * FROM plugin_versions WHERE plugin_id = $1 AND version = $2",
+        )
+        .bind(plugin_id)
+        .bind(version)
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| {
+            RegistryError::NotFound(format!(
+                "version {} of plugin {} not found",
+                version, plugin_id
+            ))
+        })
+    }
+
+    /// Highest release of the plugin that satisfies `req`.
+    pub async fn resolve(
+        db: impl PgExecutor<'_>,
+        plugin_id: i32,
+        req: &VersionReq,
+    ) -> Result<PluginVersion, RegistryError> {
+        let versions = Self::for_plugin(db, plugin_id).await?;
+
+        highest_matching(versions, req).ok_or_else(|| {
+            RegistryError::NotFound(format!(
+                "no version of plugin {} matches {}",
+                plugin_id, req
+            ))
+        })
+    }
+
+    /// Records a new release. If it is newer than the plugin's current
+    /// version, the plugin is moved forward to point at it.
+    pub async fn publish(
+        db: &PgPool,
+        plugin_id: i32,
+        new: PluginVersionNew,
+        artifact: &Artifact,
+    ) -> Result<PluginVersion, RegistryError> {
+        let version = parse_version(&new.version)?;
+        let mut tx = db.begin().await?;
+
+        let current: String =
+            sqlx::query_scalar("SELECT version FROM plugins WHERE id = $1 FOR UPDATE")
+                .bind(plugin_id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .ok_or_else(|| RegistryError::plugin_not_found(plugin_id))?;
+
+        let release = sqlx::query_as::<_, PluginVersion>(
+            "INSERT INTO plugin_versions (plugin_id, version, wasm_url, sha256, module_info, signature)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING *",
+        )
+        .bind(plugin_id)
+        .bind(&new.version)
+        .bind(&new.wasm_url)
+        .bind(&artifact.sha256)
+        .bind(Json(&artifact.module_info))
+        .bind(&new.signature)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|err| match RegistryError::from(err) {
+            RegistryError::Conflict(_) => RegistryError::Conflict(format!(
+                "version {} of plugin {} is already published",
+                new.version, plugin_id
+            )),
+            err => err,
+        })?;
+
+        if is_newer(&version, &current) {
+            sqlx::query(
+                "UPDATE plugins SET version = $1, wasm_url = $2, sha256 = $3, updated_at = now() WHERE id = $4",
+            )
+            .bind(&release.version)
+            .bind(&release.wasm_url)
+            .bind(&release.sha256)
+            .bind(plugin_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        events::notify(&mut tx, MutationKind::Update, plugin_id).await?;
+        tx.commit().await?;
+
+        Ok(release)
+    }
+}
+
+/// The highest of `versions` that satisfies `req`. Versions that don't parse
+/// never match.
+fn highest_matching(versions: Vec<PluginVersion>, req: &VersionReq) -> Option<PluginVersion> {
+    versions
+        .into_iter()
+        .filter_map(|version| Some((version.semver()?, version)))
+        .filter(|(semver, _)| req.matches(semver))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, version)| version)
+}
+
+/// Whether a new release should become the plugin's current version. A
+/// current version that doesn't parse is always moved on from.
+fn is_newer(version: &Version, current: &str) -> bool {
+    Version::parse(current).map_or(true, |current| *version > current)
+}
+
+pub fn parse_version_req(req: &str) -> Result<VersionReq, RegistryError> {
+    VersionReq::parse(req).map_err(|err| {
+        RegistryError::validation("req", format!("is not a version requirement: {}", err))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions(versions: &[&str]) -> Vec<PluginVersion> {
+        versions
+            .iter()
+            .enumerate()
+            .map(|(id, version)| PluginVersion {
+                id: id as i32,
+                plugin_id: 1,
+                version: (*version).to_owned(),
+                wasm_url: format!("https://example.com/{}.wasm", version),
+                sha256: None,
+                created_at: Utc::now(),
+                module_info: None,
+                signature: None,
+            })
+            .collect()
+    }
+
+    fn resolve(available: &[&str], req: &str) -> Option<String> {
+        let req = parse_version_req(req).unwrap();
+        highest_matching(versions(available), &req).map(|version| version.version)
+    }
+
+    #[test]
+    fn the_highest_matching_version_is_picked() {
+        let available = ["1.2.0", "1.10.1", "2.0.0", "1.9.3", "0.9.0"];
+
+        assert_eq!(resolve(&available, "^1.2").as_deref(), Some("1.10.1"));
+        assert_eq!(resolve(&available, "~1.9").as_deref(), Some("1.9.3"));
+        assert_eq!(resolve(&available, "*").as_deref(), Some("2.0.0"));
+        assert_eq!(resolve(&available, "=1.2.0").as_deref(), Some("1.2.0"));
+        assert_eq!(resolve(&available, "<1").as_deref(), Some("0.9.0"));
+        assert_eq!(resolve(&available, "^3"), None);
+        assert_eq!(resolve(&[], "*"), None);
+    }
+
+    #[test]
+    fn prereleases_only_match_when_asked_for() {
+        let available = ["1.2.0", "1.3.0-beta.1"];
+
+        assert_eq!(resolve(&available, "^1.2").as_deref(), Some("1.2.0"));
+        assert_eq!(
+            resolve(&available, ">=1.3.0-beta.0").as_deref(),
+            Some("1.3.0-beta.1")
+        );
+    }
+
+    #[test]
+    fn versions_that_do_not_parse_are_skipped() {
+        let available = ["1.2.0", "1.9", "latest", "v1.5.0", "1.3.0"];
+
+        assert_eq!(resolve(&available, "^1").as_deref(), Some("1.3.0"));
+        assert_eq!(resolve(&["latest", "1.9"], "*"), None);
+    }
+
+    #[test]
+    fn only_newer_releases_become_current() {
+        let version = |version: &str| Version::parse(version).unwrap();
+
+        assert!(is_newer(&version("1.3.0"), "1.2.9"));
+        assert!(is_newer(&version("1.10.0"), "1.9.0"));
+        assert!(is_newer(&version("2.0.0"), "2.0.0-rc.1"));
+        assert!(!is_newer(&version("1.2.0"), "1.2.0"));
+        assert!(!is_newer(&version("1.1.0"), "1.2.0"));
+        assert!(is_newer(&version("2.0.0-rc.1"), "1.10.0"));
+        assert!(is_newer(&version("0.1.0"), "not a version"));
+    }
+}
@@ -0,0 +1,348 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::{redirect, Url};
+use serde::{Deserialize, Serialize};
+use wasmparser::{ExternalKind, Parser, Payload, TypeRef, Validator};
+
+use crate::config::Config;
+use crate::error::RegistryError;
+
+const WASM_MAGIC: &[u8; 4] = b"\0asm";
+const WASM_CORE_VERSION: [u8; 4] = [1, 0, 0, 0];
+
+/// What a plugin's WASM module needs from its host and what it exposes,
+/// recorded once when a version is published.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ModuleInfo {
+    pub size: usize,
+    pub imports: Vec<Import>,
+    pub exports: Vec<Export>,
+    pub memories: Vec<Memory>,
+    pub custom_sections: Vec<CustomSection>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Import {
+    pub module: String,
+    pub name: String,
+    pub kind: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Export {
+    pub name: String,
+    pub kind: String,
+}
+
+/// Memory limits are in 64KiB pages.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Memory {
+    pub imported: bool,
+    pub initial: u64,
+    pub maximum: Option<u64>,
+    pub shared: bool,
+    pub memory64: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CustomSection {
+    pub name: String,
+    pub size: usize,
+}
+
+/// Downloads plugin artifacts with the size and time limits from [`Config`].
+/// Only public hosts are fetched from, so plugin urls can't be used to reach
+/// the registry's own network.
+#[derive(Clone)]
+pub struct WasmFetcher {
+    client: reqwest::Client,
+    max_size: usize,
+}
+
+impl WasmFetcher {
+    pub fn new(config: &Config) -> Self {
+        // Redirects are refused rather than followed, as they could lead
+        // anywhere the url checks would have turned down.
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.wasm_fetch_timeout_secs))
+            .redirect(redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()
+            .expect("Looks like the HTTP client could not be built :(");
+
+        Self {
+            client,
+            max_size: config.wasm_max_size,
+        }
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Vec<u8>, RegistryError> {
+        let download_failed =
+            |err: reqwest::Error| wasm_url_error(format!("could not be downloaded: {}", err));
+
+        // Hosts given as names are checked as they resolve, by the client.
+        let parsed = Url::parse(url)
+            .map_err(|err| wasm_url_error(format!("is not a valid url: {}", err)))?;
+        if has_private_ip(&parsed) {
+            return Err(wasm_url_error("must not point at a private address"));
+        }
+
+        let mut response = self
+            .client
+            .get(parsed)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(download_failed)?;
+
+        if response.status().is_redirection() {
+            return Err(wasm_url_error(
+                "redirects elsewhere, link to the module itself instead",
+            ));
+        }
+
+        if response
+            .content_length()
+            .is_some_and(|length| length > self.max_size as u64)
+        {
+            return Err(self.too_large());
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(download_failed)? {
+            if bytes.len() + chunk.len() > self.max_size {
+                return Err(self.too_large());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(bytes)
+    }
+
+    fn too_large(&self) -> RegistryError {
+        wasm_url_error(format!("is larger than {} bytes", self.max_size))
+    }
+}
+
+/// Resolves hosts to their public addresses only, which also covers names
+/// that change what they resolve to after the url was checked.
+pub(crate) struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether the url's host is an IP address that isn't public. Hosts given as
+/// names are left to [`PublicResolver`].
+pub(crate) fn has_private_ip(url: &Url) -> bool {
+    match url.host() {
+        Some(url::Host::Ipv4(ip)) => !is_public(IpAddr::V4(ip)),
+        Some(url::Host::Ipv6(ip)) => !is_public(IpAddr::V6(ip)),
+        Some(url::Host::Domain(_)) | None => false,
+    }
+}
+
+/// Whether the address is reachable on the internet, rather than being
+/// loopback, private, link-local or otherwise reserved.
+pub(crate) fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || is_shared(ip))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Carrier-grade NAT space, 100.64.0.0/10.
+fn is_shared(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    first == 100 && second & 0b1100_0000 == 0b0100_0000
+}
+
+fn wasm_url_error(message: impl Into<String>) -> RegistryError {
+    RegistryError::validation("wasm_url", message)
+}
+
+/// Checks the module header, validates the whole module and collects its
+/// imports, exports, memories and custom sections.
+pub fn inspect(bytes: &[u8]) -> Result<ModuleInfo, String> {
+    if bytes.len() < 8 || &bytes[0..4] != WASM_MAGIC {
+        return Err("is not a WebAssembly module (bad magic number)".to_owned());
+    }
+    if bytes[4..8] != WASM_CORE_VERSION {
+        return Err(format!(
+            "has unsupported WebAssembly version {:?}, only core modules (version 1) are accepted",
+            &bytes[4..8]
+        ));
+    }
+
+    Validator::new()
+        .validate_all(bytes)
+        .map_err(|err| format!("is not a valid WebAssembly module: {}", err))?;
+
+    let mut info = ModuleInfo {
+        size: bytes.len(),
+        ..ModuleInfo::default()
+    };
+
+    for payload in Parser::new(0).parse_all(bytes) {
+        let payload = payload.map_err(|err| format!("could not be parsed: {}", err))?;
+
+        match payload {
+            Payload::ImportSection(reader) => {
+                for import in reader.into_imports() {
+                    let import = import.map_err(|err| format!("has a bad import: {}", err))?;
+                    if let TypeRef::Memory(memory) = import.ty {
+                        info.memories.push(Memory {
+                            imported: true,
+                            initial: memory.initial,
+                            maximum: memory.maximum,
+                            shared: memory.shared,
+                            memory64: memory.memory64,
+                        });
+                    }
+                    info.imports.push(Import {
+                        module: import.module.to_owned(),
+                        name: import.name.to_owned(),
+                        kind: type_ref_kind(&import.ty).to_owned(),
+                    });
+                }
+            }
+            Payload::MemorySection(reader) => {
+                for memory in reader {
+                    let memory = memory.map_err(|err| format!("has a bad memory: {}", err))?;
+                    info.memories.push(Memory {
+                        imported: false,
+                        initial: memory.initial,
+                        maximum: memory.maximum,
+                        shared: memory.shared,
+                        memory64: memory.memory64,
+                    });
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export.map_err(|err| format!("has a bad export: {}", err))?;
+                    info.exports.push(Export {
+                        name: export.name.to_owned(),
+                        kind: external_kind(export.kind).to_owned(),
+                    });
+                }
+            }
+            Payload::CustomSection(reader) => {
+                info.custom_sections.push(CustomSection {
+                    name: reader.name().to_owned(),
+                    size: reader.data().len(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(info)
+}
+
+fn type_ref_kind(ty: &TypeRef) -> &'static str {
+    match ty {
+        TypeRef::Func(_) | TypeRef::FuncExact(_) => "func",
+        TypeRef::Table(_) => "table",
+        TypeRef::Memory(_) => "memory",
+        TypeRef::Global(_) => "global",
+        TypeRef::Tag(_) => "tag",
+    }
+}
+
+fn external_kind(kind: ExternalKind) -> &'static str {
+    match kind {
+        ExternalKind::Func | ExternalKind::FuncExact => "func",
+        ExternalKind::Table => "table",
+        ExternalKind::Memory => "memory",
+        ExternalKind::Global => "global",
+        ExternalKind::Tag => "tag",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_are_fetched_from() {
+        for public in ["93.184.216.34", "100.128.0.1", "2606:2800:220:1::1"] {
+            assert!(is_public(public.parse().unwrap()), "{}", public);
+        }
+        for private in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(private.parse().unwrap()), "{}", private);
+        }
+    }
+
+    #[tokio::test]
+    async fn private_hosts_are_refused() {
+        let fetcher = WasmFetcher::new(&Config::default());
+        // Something to connect to, so only the host checks can fail.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        for (url, reason) in [
+            (
+                format!("http://127.0.0.1:{}/plugin.wasm", port),
+                "private address",
+            ),
+            (
+                format!("http://[::ffff:127.0.0.1]:{}/plugin.wasm", port),
+                "private address",
+            ),
+            (
+                format!("http://localhost:{}/plugin.wasm", port),
+                "no public address",
+            ),
+        ] {
+            let err = fetcher.fetch(&url).await.unwrap_err();
+            assert!(err.message().contains(reason), "{}: {:?}", url, err);
+        }
+    }
+}
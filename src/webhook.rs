@@ -0,0 +1,711 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+use hmac::{Hmac, Mac};
+use reqwest::{header, redirect, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::types::Json;
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::Config;
+use crate::error::RegistryError;
+use crate::plugin::{empty_string_as_none, Plugin};
+use crate::wasm::{self, PublicResolver};
+use crate::{MutationKind, PluginUpdate, PluginsStream};
+
+/// Webhook secrets start with this, which makes them easy to spot in leaked
+/// config.
+pub const SECRET_PREFIX: &str = "whsec_";
+
+/// How often the queue is checked for retries that came due.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Deliveries each instance sends at once.
+const BATCH_SIZE: i64 = 16;
+/// Longest wait between two attempts at a delivery.
+const MAX_RETRY_DELAY_SECS: u64 = 24 * 60 * 60;
+/// Deliveries shown in a webhook's log.
+const LOG_SIZE: i64 = 50;
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    #[serde(skip)]
+    pub secret: String,
+    /// Kinds of change delivered: `create`, `update` and `delete`.
+    pub events: Vec<String>,
+    pub plugin_id: Option<i32>,
+    pub tag: Option<String>,
+    /// Username of the owner of the plugins whose changes are delivered.
+    pub owner: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A webhook as returned right after creating it, the only time the secret
+/// is shown.
+#[derive(Serialize)]
+pub struct NewWebhook {
+    pub secret: String,
+    #[serde(flatten)]
+    pub details: Webhook,
+}
+
+#[derive(Deserialize)]
+pub struct WebhookNew {
+    pub url: String,
+    pub events: Vec<MutationKind>,
+    pub plugin_id: Option<i32>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub tag: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub owner: Option<String>,
+}
+
+/// One event sent, or still to be sent, to a webhook.
+#[derive(sqlx::FromRow, Serialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    /// `create`, `update`, `delete` or `ping`.
+    pub event: String,
+    pub payload: Json<serde_json::Value>,
+    pub attempts: i32,
+    /// Missing once delivered or given up on.
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    /// Status the webhook answered the last attempt with.
+    pub response_status: Option<i32>,
+    /// Why the last attempt got no response.
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WebhookDelivery {
+    pub fn status(&self) -> &'static str {
+        match (self.delivered_at, self.next_attempt_at) {
+            (Some(_), _) => "delivered",
+            (None, Some(_)) if self.attempts == 0 => "pending",
+            (None, Some(_)) => "retrying",
+            (None, None) => "failed",
+        }
+    }
+}
+
+impl Webhook {
+    /// The first characters of the secret, for telling webhooks apart.
+    pub fn secret_prefix(&self) -> &str {
+        &self.secret[..SECRET_PREFIX.len() + 8]
+    }
+
+    pub async fn for_user(
+        db: impl PgExecutor<'_>,
+        user_id: i32,
+    ) -> Result<Vec<Webhook>, RegistryError> {
+        let webhooks = sqlx::query_as::<_, Webhook>(
+            "SELECT * FROM webhooks WHERE user_id = $1 ORDER BY created_at DESC, id DESC",
+        )
+        .bind(user_id)
+        .fetch_all(db)
+        .await?;
+
+        Ok(webhooks)
+    }
+
+    /// One of the user's webhooks.
+    pub async fn find(
+        db: impl PgExecutor<'_>,
+        user_id: i32,
+        id: i32,
+    ) -> Result<Webhook, RegistryError> {
+        sqlx::query_as::<_, Webhook>("SELECT * FROM webhooks WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?
+            .ok_or_else(|| RegistryError::NotFound(format!("webhook {} not found", id)))
+    }
+
+    pub async fn create(
+        db: impl PgExecutor<'_>,
+        user_id: i32,
+        new: WebhookNew,
+    ) -> Result<NewWebhook, RegistryError> {
+        let secret = format!(
+            "{}{}",
+            SECRET_PREFIX,
+            hex::encode(rand::random::<[u8; 32]>())
+        );
+        let events: Vec<_> = new.events.iter().map(|kind| kind.event_name()).collect();
+
+        let details = sqlx::query_as::<_, Webhook>(
+            "INSERT INTO webhooks (user_id, url, secret, events, plugin_id, tag, owner)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING *",
+        )
+        .bind(user_id)
+        .bind(new.url)
+        .bind(&secret)
+        .bind(events)
+        .bind(new.plugin_id)
+        .bind(new.tag)
+        .bind(new.owner)
+        .fetch_one(db)
+        .await?;
+
+        Ok(NewWebhook { secret, details })
+    }
+
+    /// Removes one of the user's webhooks along with its deliveries.
+    pub async fn delete(
+        db: impl PgExecutor<'_>,
+        user_id: i32,
+        id: i32,
+    ) -> Result<(), RegistryError> {
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(db)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(RegistryError::NotFound(format!("webhook {} not found", id)));
+        }
+
+        Ok(())
+    }
+
+    /// The latest deliveries, newest first.
+    pub async fn deliveries(
+        &self,
+        db: impl PgExecutor<'_>,
+    ) -> Result<Vec<WebhookDelivery>, RegistryError> {
+        let deliveries = sqlx::query_as::<_, WebhookDelivery>(
+            "SELECT * FROM webhook_deliveries WHERE webhook_id = $1 ORDER BY id DESC LIMIT $2",
+        )
+        .bind(self.id)
+        .bind(LOG_SIZE)
+        .fetch_all(db)
+        .await?;
+
+        Ok(deliveries)
+    }
+
+    /// Queues a `ping` delivery, for checking the receiving end works.
+    pub async fn ping(&self, db: impl PgExecutor<'_>) -> Result<WebhookDelivery, RegistryError> {
+        let delivery = sqlx::query_as::<_, WebhookDelivery>(
+            "INSERT INTO webhook_deliveries (webhook_id, event, payload)
+             VALUES ($1, 'ping', $2)
+             RETURNING *",
+        )
+        .bind(self.id)
+        .bind(Json(json!({ "event": "ping", "webhook_id": self.id })))
+        .fetch_one(db)
+        .await?;
+
+        Ok(delivery)
+    }
+}
+
+/// Queues a delivery of a logged change for every webhook after it, in the
+/// transaction making the change. The plugin is sent as it is at that
+/// point, so deletions have to be logged before the plugin is gone.
+pub async fn enqueue(conn: &mut PgConnection, update: &PluginUpdate) -> Result<(), RegistryError> {
+    let plugin = Plugin::find(&mut *conn, update.id).await?;
+    let event = update.mutation_kind.event_name();
+    let payload = json!({
+        "event": event,
+        "event_id": update.event_id,
+        "plugin": plugin,
+    });
+
+    sqlx::query(
+        "INSERT INTO webhook_deliveries (webhook_id, event, payload)
+         SELECT id, $1, $2 FROM webhooks
+         WHERE $1 = ANY(events)
+         AND (plugin_id IS NULL OR plugin_id = $3)
+         AND (tag IS NULL OR tag = ANY($4))
+         AND (owner IS NULL OR owner = $5)",
+    )
+    .bind(event)
+    .bind(Json(payload))
+    .bind(plugin.id)
+    .bind(&plugin.tags)
+    .bind(&plugin.owner)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `<timestamp>.<body>` keyed with the
+/// webhook's secret, sent as `X-Registry-Signature`. The timestamp, sent as
+/// `X-Registry-Timestamp`, lets receivers turn away replayed deliveries.
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Sends queued deliveries until the registry shuts down. Every instance
+/// runs this; a delivery is only ever claimed by one of them at a time.
+pub fn spawn(db: PgPool, config: &Config, tx: &PluginsStream) {
+    let mut rx = tx.subscribe();
+    let dispatcher = Dispatcher::new(config);
+
+    tokio::spawn(async move {
+        loop {
+            if let Err(err) = dispatcher.deliver_due(&db).await {
+                eprintln!("Couldn't deliver webhooks: {:?}", err);
+            }
+
+            // Changes wake the queue up early; retries come due on their own.
+            tokio::select! {
+                result = rx.recv() => {
+                    if let Err(RecvError::Closed) = result {
+                        break;
+                    }
+                }
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    });
+}
+
+/// A delivery claimed for an attempt.
+#[derive(sqlx::FromRow)]
+struct Attempt {
+    id: i64,
+    event: String,
+    payload: Json<serde_json::Value>,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+/// How an attempt went, as recorded on its delivery.
+struct Outcome {
+    attempts: i32,
+    /// When to try again, unless delivered or given up on.
+    next_attempt_at: Option<DateTime<Utc>>,
+    delivered_at: Option<DateTime<Utc>>,
+    response_status: Option<i32>,
+    error: Option<String>,
+}
+
+/// Sends deliveries to public hosts only, so webhooks can't be used to reach
+/// the registry's own network.
+struct Dispatcher {
+    client: reqwest::Client,
+    timeout: Duration,
+    max_attempts: i32,
+    retry_base_secs: u64,
+    private_hosts: bool,
+}
+
+impl Dispatcher {
+    fn new(config: &Config) -> Self {
+        Self::build(config, false)
+    }
+
+    /// A dispatcher that also delivers to private hosts, for receivers
+    /// running next to the tests.
+    #[cfg(test)]
+    fn allowing_private_hosts(config: &Config) -> Self {
+        Self::build(config, true)
+    }
+
+    fn build(config: &Config, private_hosts: bool) -> Self {
+        let timeout = Duration::from_secs(config.webhook_timeout_secs);
+        // Redirects count as failures rather than sending deliveries on to
+        // wherever they point.
+        let mut client = reqwest::Client::builder()
+            .timeout(timeout)
+            .redirect(redirect::Policy::none());
+        if !private_hosts {
+            client = client.dns_resolver(Arc::new(PublicResolver));
+        }
+
+        Self {
+            client: client
+                .build()
+                .expect("Looks like the HTTP client could not be built :("),
+            timeout,
+            max_attempts: config.webhook_max_attempts,
+            retry_base_secs: config.webhook_retry_base_secs,
+            private_hosts,
+        }
+    }
+
+    async fn deliver_due(&self, db: &PgPool) -> Result<(), RegistryError> {
+        loop {
+            let attempts = self.claim(db).await?;
+            join_all(attempts.iter().map(|attempt| self.attempt(db, attempt))).await;
+
+            if attempts.len() < BATCH_SIZE as usize {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Takes deliveries that are due. They are put off for as long as an
+    /// attempt can take, so if this instance goes away meanwhile another one
+    /// retries them.
+    async fn claim(&self, db: &PgPool) -> Result<Vec<Attempt>, RegistryError> {
+        let attempts = sqlx::query_as::<_, Attempt>(
+            "WITH claimed AS (
+                UPDATE webhook_deliveries SET next_attempt_at = now() + make_interval(secs => $1)
+                WHERE id IN (
+                    SELECT id FROM webhook_deliveries WHERE next_attempt_at <= now()
+                    ORDER BY next_attempt_at LIMIT $2
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id, webhook_id, event, payload, attempts
+             )
+             SELECT claimed.*, webhooks.url, webhooks.secret
+             FROM claimed JOIN webhooks ON webhooks.id = claimed.webhook_id",
+        )
+        .bind(self.timeout.as_secs_f64() * 2.0)
+        .bind(BATCH_SIZE)
+        .fetch_all(db)
+        .await?;
+
+        Ok(attempts)
+    }
+
+    /// Posts the delivery and records how it went.
+    async fn attempt(&self, db: &PgPool, attempt: &Attempt) {
+        let outcome = self.send(attempt).await;
+
+        let recorded = sqlx::query(
+            "UPDATE webhook_deliveries
+             SET attempts = $2, next_attempt_at = $3, delivered_at = $4, response_status = $5, error = $6
+             WHERE id = $1",
+        )
+        .bind(attempt.id)
+        .bind(outcome.attempts)
+        .bind(outcome.next_attempt_at)
+        .bind(outcome.delivered_at)
+        .bind(outcome.response_status)
+        .bind(&outcome.error)
+        .execute(db)
+        .await;
+
+        if let Err(err) = recorded {
+            eprintln!(
+                "Couldn't record the attempt at webhook delivery {}: {}",
+                attempt.id, err
+            );
+        }
+    }
+
+    /// Posts the delivery, scheduling a retry if it failed.
+    async fn send(&self, attempt: &Attempt) -> Outcome {
+        // Checked when the webhook was added, but it may predate the check.
+        // Retrying wouldn't change the address, so it is given up on.
+        if !self.private_hosts
+            && Url::parse(&attempt.url).is_ok_and(|url| wasm::has_private_ip(&url))
+        {
+            return Outcome {
+                attempts: attempt.attempts + 1,
+                next_attempt_at: None,
+                delivered_at: None,
+                response_status: None,
+                error: Some("the url points at a private address".to_owned()),
+            };
+        }
+
+        let body = attempt.payload.0.to_string();
+        let timestamp = Utc::now().timestamp();
+        let response = self
+            .client
+            .post(&attempt.url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("X-Registry-Event", &attempt.event)
+            .header("X-Registry-Delivery", attempt.id.to_string())
+            .header("X-Registry-Timestamp", timestamp.to_string())
+            .header(
+                "X-Registry-Signature",
+                signature(&attempt.secret, timestamp, body.as_bytes()),
+            )
+            .body(body)
+            .send()
+            .await;
+
+        let (delivered, status, error) = match response {
+            Ok(response) => (
+                response.status().is_success(),
+                Some(response.status().as_u16()),
+                None,
+            ),
+            Err(err) => (false, None, Some(err.to_string())),
+        };
+
+        let attempts = attempt.attempts + 1;
+        Outcome {
+            attempts,
+            next_attempt_at: (!delivered && attempts < self.max_attempts)
+                .then(|| Utc::now() + self.retry_delay(attempts)),
+            delivered_at: delivered.then(Utc::now),
+            response_status: status.map(i32::from),
+            error,
+        }
+    }
+
+    /// Wait after the given number of failed attempts, doubling every time.
+    fn retry_delay(&self, attempts: i32) -> chrono::Duration {
+        let secs = 2u64
+            .saturating_pow(attempts.saturating_sub(1) as u32)
+            .saturating_mul(self.retry_base_secs)
+            .min(MAX_RETRY_DELAY_SECS);
+
+        chrono::Duration::seconds(secs as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use axum::body::Bytes;
+    use axum::extract::State;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+
+    const SECRET: &str = "whsec_test";
+
+    fn dispatcher(max_attempts: i32) -> Dispatcher {
+        Dispatcher::allowing_private_hosts(&Config {
+            webhook_timeout_secs: 5,
+            webhook_max_attempts: max_attempts,
+            webhook_retry_base_secs: 30,
+            ..Config::default()
+        })
+    }
+
+    #[test]
+    fn signatures_cover_the_timestamp_and_body() {
+        let body = br#"{"event":"ping","webhook_id":1}"#;
+
+        // HMAC-SHA256("whsec_test", "1700000000.{...}")
+        assert_eq!(
+            signature(SECRET, 1_700_000_000, body),
+            "sha256=1af09c79b32ab250b2e9abb16c33ca7a7c93e9fcfca6dcb6fa5132337a6f7adc"
+        );
+        assert_ne!(
+            signature(SECRET, 1_700_000_001, body),
+            signature(SECRET, 1_700_000_000, body)
+        );
+        assert_ne!(
+            signature("whsec_other", 1_700_000_000, body),
+            signature(SECRET, 1_700_000_000, body)
+        );
+    }
+
+    #[test]
+    fn retries_back_off_up_to_a_day() {
+        let dispatcher = dispatcher(8);
+        let delays: Vec<i64> = (1..=5)
+            .map(|attempts| dispatcher.retry_delay(attempts).num_seconds())
+            .collect();
+        assert_eq!(delays, [30, 60, 120, 240, 480]);
+
+        let max = MAX_RETRY_DELAY_SECS as i64;
+        assert_eq!(dispatcher.retry_delay(12).num_seconds(), 30 << 11);
+        assert_eq!(dispatcher.retry_delay(13).num_seconds(), max);
+        assert_eq!(dispatcher.retry_delay(64).num_seconds(), max);
+        assert_eq!(dispatcher.retry_delay(i32::MAX).num_seconds(), max);
+    }
+
+    /// A webhook answering with `statuses` in turn, repeating the last one,
+    /// that keeps the headers and body of every request.
+    #[derive(Default)]
+    struct Receiver {
+        statuses: Vec<u16>,
+        received: Mutex<Vec<(HeaderMap, Bytes)>>,
+    }
+
+    async fn receive(
+        State(receiver): State<Arc<Receiver>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> StatusCode {
+        let mut received = receiver.received.lock().unwrap();
+        received.push((headers, body));
+        let status = receiver.statuses[(received.len() - 1).min(receiver.statuses.len() - 1)];
+        StatusCode::from_u16(status).unwrap()
+    }
+
+    fn serve(statuses: &[u16]) -> (Arc<Receiver>, SocketAddr) {
+        let receiver = Arc::new(Receiver {
+            statuses: statuses.to_vec(),
+            ..Receiver::default()
+        });
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(receiver.clone());
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        (receiver, addr)
+    }
+
+    /// Attempts the delivery the way the queue does until it is delivered or
+    /// given up on, returning the outcome of each attempt.
+    async fn deliver(dispatcher: &Dispatcher, addr: SocketAddr) -> Vec<Outcome> {
+        deliver_to(dispatcher, format!("http://{}/hook", addr)).await
+    }
+
+    async fn deliver_to(dispatcher: &Dispatcher, url: String) -> Vec<Outcome> {
+        let mut attempt = Attempt {
+            id: 7,
+            event: "ping".to_owned(),
+            payload: Json(json!({ "event": "ping", "webhook_id": 1 })),
+            attempts: 0,
+            url,
+            secret: SECRET.to_owned(),
+        };
+
+        let mut outcomes = Vec::new();
+        loop {
+            let outcome = dispatcher.send(&attempt).await;
+            attempt.attempts = outcome.attempts;
+            let done = outcome.next_attempt_at.is_none();
+            outcomes.push(outcome);
+            if done {
+                return outcomes;
+            }
+        }
+    }
+
+    fn assert_signed(received: &[(HeaderMap, Bytes)]) {
+        for (headers, body) in received {
+            let header = |name: &str| headers[name].to_str().unwrap();
+            let timestamp: i64 = header("x-registry-timestamp").parse().unwrap();
+
+            assert!((Utc::now().timestamp() - timestamp).abs() < 60);
+            assert_eq!(
+                header("x-registry-signature"),
+                signature(SECRET, timestamp, body)
+            );
+            assert_eq!(header("x-registry-event"), "ping");
+            assert_eq!(header("x-registry-delivery"), "7");
+            assert_eq!(header("content-type"), "application/json");
+            assert_eq!(
+                serde_json::from_slice::<serde_json::Value>(body).unwrap(),
+                json!({ "event": "ping", "webhook_id": 1 })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn successful_deliveries_are_done() {
+        let (receiver, addr) = serve(&[204]);
+        let outcomes = deliver(&dispatcher(3), addr).await;
+
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].attempts, 1);
+        assert!(outcomes[0].delivered_at.is_some());
+        assert_eq!(outcomes[0].response_status, Some(204));
+        assert_eq!(outcomes[0].error, None);
+        assert_signed(&receiver.received.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_are_retried() {
+        let (receiver, addr) = serve(&[500, 200]);
+        let before = Utc::now();
+        let outcomes = deliver(&dispatcher(3), addr).await;
+
+        assert_eq!(outcomes.len(), 2);
+        let retry_at = outcomes[0].next_attempt_at.unwrap();
+        assert!(retry_at >= before + chrono::Duration::seconds(30));
+        assert!(retry_at <= Utc::now() + chrono::Duration::seconds(30));
+        assert_eq!(outcomes[0].delivered_at, None);
+        assert_eq!(outcomes[0].response_status, Some(500));
+
+        assert_eq!(outcomes[1].attempts, 2);
+        assert!(outcomes[1].delivered_at.is_some());
+        assert_eq!(outcomes[1].response_status, Some(200));
+
+        let received = receiver.received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_signed(&received);
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_given_up_after_the_last_attempt() {
+        let (receiver, addr) = serve(&[500]);
+        let outcomes = deliver(&dispatcher(3), addr).await;
+
+        assert_eq!(
+            outcomes
+                .iter()
+                .map(|outcome| outcome.attempts)
+                .collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        let last = outcomes.last().unwrap();
+        assert_eq!(last.next_attempt_at, None);
+        assert_eq!(last.delivered_at, None);
+        assert_eq!(last.response_status, Some(500));
+        assert_eq!(receiver.received.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn unreachable_webhooks_are_retried() {
+        // Nothing listens on the port once the listener is gone.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let outcomes = deliver(&dispatcher(2), addr).await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes[0].next_attempt_at.is_some());
+        for outcome in &outcomes {
+            assert_eq!(outcome.response_status, None);
+            assert!(outcome.error.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn private_hosts_are_not_delivered_to() {
+        let (receiver, addr) = serve(&[200]);
+        let dispatcher = Dispatcher::new(&Config {
+            webhook_max_attempts: 3,
+            ..Config::default()
+        });
+
+        // A literal address is given up on straight away.
+        let outcomes = deliver(&dispatcher, addr).await;
+        assert_eq!(outcomes.len(), 1);
+        assert!(outcomes[0]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("private address"));
+
+        // A name is refused as it resolves, and retried in case it changes.
+        let url = format!("http://localhost:{}/hook", addr.port());
+        let outcomes = deliver_to(&dispatcher, url).await;
+        assert_eq!(outcomes.len(), 3);
+        for outcome in &outcomes {
+            assert_eq!(outcome.delivered_at, None);
+            assert_eq!(outcome.response_status, None);
+        }
+
+        assert!(receiver.received.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{rejection::WebSocketUpgradeRejection, Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::time::{interval_at, timeout, Instant};
+
+use crate::error::RegistryError;
+use crate::plugin::Plugin;
+use crate::{AppState, MutationKind, PluginUpdate, PluginsStream};
+
+/// How often connections are pinged.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+/// Connections that stay silent for this long, not even answering pings, are
+/// closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// How long a client gets to take a message before it is dropped as too slow
+/// to keep up.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_SUBSCRIPTIONS: usize = 32;
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Which changes a subscription is after. Every condition given has to hold.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filter {
+    plugin_id: Option<i32>,
+    tag: Option<String>,
+    /// Username of the plugin's owner.
+    owner: Option<String>,
+    mutation_kinds: Option<Vec<MutationKind>>,
+}
+
+impl Filter {
+    /// Whether telling if a plugin matches takes looking at the plugin.
+    fn by_plugin(&self) -> bool {
+        self.tag.is_some() || self.owner.is_some()
+    }
+
+    fn matches(&self, plugin: &Plugin) -> bool {
+        self.tag
+            .as_ref()
+            .is_none_or(|tag| plugin.tags.contains(tag))
+            && self
+                .owner
+                .as_ref()
+                .is_none_or(|owner| plugin.owner.as_ref() == Some(owner))
+    }
+}
+
+struct Subscription {
+    filter: Filter,
+    /// Plugins matching a tag or owner filter, as deleted plugins can't be
+    /// looked at anymore.
+    matching: HashSet<i32>,
+}
+
+impl Subscription {
+    async fn new(db: &PgPool, filter: Filter) -> Result<Self, RegistryError> {
+        let matching = if filter.by_plugin() {
+            sqlx::query_scalar(
+                "SELECT plugins.id FROM plugins LEFT JOIN users ON users.id = plugins.owner_id
+                 WHERE ($1::INT IS NULL OR plugins.id = $1)
+                 AND ($2::TEXT IS NULL OR EXISTS (
+                     SELECT 1 FROM plugin_tags JOIN tags ON tags.id = plugin_tags.tag_id
+                     WHERE plugin_tags.plugin_id = plugins.id AND tags.name = $2
+                 ))
+                 AND ($3::TEXT IS NULL OR users.username = $3)",
+            )
+            .bind(filter.plugin_id)
+            .bind(&filter.tag)
+            .bind(&filter.owner)
+            .fetch_all(db)
+            .await?
+            .into_iter()
+            .collect()
+        } else {
+            HashSet::new()
+        };
+
+        Ok(Self { filter, matching })
+    }
+
+    /// Whether telling if the subscriber is after `update` takes looking at
+    /// the plugin. Tag and owner filters follow which plugins match through
+    /// changes they don't deliver too, so they can tell when one stops
+    /// matching; only filters delivering nothing but creations can skip that.
+    fn needs_plugin(&self, update: &PluginUpdate) -> bool {
+        update.mutation_kind != MutationKind::Delete
+            && self.filter.by_plugin()
+            && self.filter.plugin_id.is_none_or(|id| id == update.id)
+            && self.filter.mutation_kinds.as_ref().is_none_or(|kinds| {
+                kinds.contains(&update.mutation_kind)
+                    || kinds.iter().any(|kind| *kind != MutationKind::Create)
+            })
+    }
+
+    /// Whether the subscriber is after the change. `plugin` is the plugin as
+    /// it is now, missing for deletions and when no subscription on the
+    /// connection [needs it](Subscription::needs_plugin).
+    fn wants(&mut self, update: &PluginUpdate, plugin: Option<&Plugin>) -> bool {
+        if self.filter.plugin_id.is_some_and(|id| id != update.id) {
+            return false;
+        }
+
+        let kind_wanted = self
+            .filter
+            .mutation_kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&update.mutation_kind));
+        if !self.filter.by_plugin() {
+            return kind_wanted;
+        }
+
+        let matches = match (update.mutation_kind, plugin) {
+            (MutationKind::Delete, _) => false,
+            (_, Some(plugin)) => self.filter.matches(plugin),
+            // Deleted since, which is up next, or not looked up as only
+            // creations are delivered.
+            (_, None) => return false,
+        };
+        let matched = if matches {
+            !self.matching.insert(update.id)
+        } else {
+            self.matching.remove(&update.id)
+        };
+        // Changes that take a plugin out of the filter are still sent, so
+        // subscribers learn it no longer matches.
+        if !matches && !matched {
+            return false;
+        }
+
+        kind_wanted
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Starts a subscription, or replaces the one with the same id.
+    Subscribe {
+        id: String,
+        #[serde(default)]
+        filter: Filter,
+    },
+    Unsubscribe {
+        id: String,
+    },
+    Ping,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Subscribed {
+        id: String,
+    },
+    Unsubscribed {
+        id: String,
+    },
+    /// A change, with the ids of every subscription it is for.
+    Update {
+        subscriptions: Vec<String>,
+        update: PluginUpdate,
+    },
+    /// Changes were missed, so whatever the client keeps needs reloading.
+    Lagged {
+        missed: u64,
+    },
+    Pong,
+    Error {
+        id: Option<String>,
+        message: String,
+    },
+}
+
+/// Subscriptions to plugin changes over a WebSocket, for agents that track
+/// the registry. Clients send JSON messages to subscribe with a [`Filter`]
+/// and to unsubscribe, and get each matching change once, along with the
+/// subscriptions it matched.
+pub async fn handle_socket(
+    State(state): State<AppState>,
+    Extension(tx): Extension<PluginsStream>,
+    ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Result<Response, RegistryError> {
+    let rx = tx.subscribe();
+
+    Ok(ws?
+        .max_message_size(MAX_MESSAGE_SIZE)
+        .on_upgrade(move |socket| {
+            Connection {
+                state,
+                socket,
+                subscriptions: HashMap::new(),
+            }
+            .run(rx)
+        }))
+}
+
+struct Connection {
+    state: AppState,
+    socket: WebSocket,
+    subscriptions: HashMap<String, Subscription>,
+}
+
+impl Connection {
+    async fn run(mut self, mut rx: Receiver<PluginUpdate>) {
+        let mut ping = interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+        let mut last_seen = Instant::now();
+
+        loop {
+            let result = tokio::select! {
+                message = self.socket.recv() => match message {
+                    Some(Ok(message)) => {
+                        last_seen = Instant::now();
+                        self.receive(message).await
+                    }
+                    // Closed by the client, or the connection broke.
+                    _ => break,
+                },
+                update = rx.recv() => match update {
+                    Ok(update) => self.update(update).await,
+                    Err(RecvError::Lagged(missed)) => {
+                        self.state.metrics.record_lag(missed);
+                        self.send(ServerMessage::Lagged { missed }).await
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = ping.tick() => {
+                    if last_seen.elapsed() > IDLE_TIMEOUT {
+                        break;
+                    }
+                    self.send_message(Message::Ping(Vec::new())).await
+                }
+            };
+
+            if result.is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn receive(&mut self, message: Message) -> Result<(), axum::Error> {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Binary(_) => {
+                return self.error(None, "messages must be JSON text").await;
+            }
+            // Pings are answered by the socket itself.
+            Message::Ping(_) | Message::Pong(_) | Message::Close(_) => return Ok(()),
+        };
+
+        match serde_json::from_str(&text) {
+            Ok(ClientMessage::Subscribe { id, filter }) => self.subscribe(id, filter).await,
+            Ok(ClientMessage::Unsubscribe { id }) => match self.subscriptions.remove(&id) {
+                Some(_) => self.send(ServerMessage::Unsubscribed { id }).await,
+                None => self.error(Some(id), "no such subscription").await,
+            },
+            Ok(ClientMessage::Ping) => self.send(ServerMessage::Pong).await,
+            Err(err) => self.error(None, format!("invalid message: {}", err)).await,
+        }
+    }
+
+    async fn subscribe(&mut self, id: String, filter: Filter) -> Result<(), axum::Error> {
+        if !self.subscriptions.contains_key(&id) && self.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+            return self
+                .error(
+                    Some(id),
+                    format!("at most {} subscriptions are allowed", MAX_SUBSCRIPTIONS),
+                )
+                .await;
+        }
+
+        match Subscription::new(&self.state.db, filter).await {
+            Ok(subscription) => {
+                self.subscriptions.insert(id.clone(), subscription);
+                self.send(ServerMessage::Subscribed { id }).await
+            }
+            Err(err) => {
+                eprintln!("Couldn't subscribe to plugin changes: {:?}", err);
+                self.error(Some(id), "couldn't subscribe, try again").await
+            }
+        }
+    }
+
+    async fn update(&mut self, update: PluginUpdate) -> Result<(), axum::Error> {
+        // Looked up once for every connection, and only if a subscription
+        // can't tell without it.
+        let plugin = if self
+            .subscriptions
+            .values()
+            .any(|subscription| subscription.needs_plugin(&update))
+        {
+            match self.state.snapshots.plugin(&self.state.db, &update).await {
+                Ok(plugin) => plugin,
+                Err(err) => {
+                    eprintln!("Couldn't filter change to plugin {}: {:?}", update.id, err);
+                    return self.send(ServerMessage::Lagged { missed: 1 }).await;
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut subscriptions: Vec<String> = self
+            .subscriptions
+            .iter_mut()
+            .filter_map(|(id, subscription)| {
+                subscription
+                    .wants(&update, plugin.as_deref())
+                    .then(|| id.clone())
+            })
+            .collect();
+        if subscriptions.is_empty() {
+            return Ok(());
+        }
+        subscriptions.sort();
+
+        self.send(ServerMessage::Update {
+            subscriptions,
+            update,
+        })
+        .await
+    }
+
+    async fn error(
+        &mut self,
+        id: Option<String>,
+        message: impl Into<String>,
+    ) -> Result<(), axum::Error> {
+        self.send(ServerMessage::Error {
+            id,
+            message: message.into(),
+        })
+        .await
+    }
+
+    async fn send(&mut self, message: ServerMessage) -> Result<(), axum::Error> {
+        self.send_message(Message::Text(json!(message).to_string()))
+            .await
+    }
+
+    /// Sends a message, giving up on clients that don't take it in time
+    /// rather than buffering for them.
+    async fn send_message(&mut self, message: Message) -> Result<(), axum::Error> {
+        timeout(SEND_TIMEOUT, self.socket.send(message))
+            .await
+            .map_err(axum::Error::new)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscription(filter: serde_json::Value) -> Subscription {
+        Subscription {
+            filter: serde_json::from_value(filter).unwrap(),
+            matching: HashSet::new(),
+        }
+    }
+
+    fn update(mutation_kind: MutationKind, id: i32) -> PluginUpdate {
+        PluginUpdate {
+            event_id: 1,
+            mutation_kind,
+            id,
+        }
+    }
+
+    #[test]
+    fn plugins_are_only_looked_up_for_tag_and_owner_filters() {
+        let changed = update(MutationKind::Update, 1);
+
+        assert!(!subscription(json!({})).needs_plugin(&changed));
+        assert!(!subscription(json!({ "plugin_id": 1 })).needs_plugin(&changed));
+        assert!(!subscription(json!({ "mutation_kinds": ["update"] })).needs_plugin(&changed));
+        assert!(subscription(json!({ "tag": "ai" })).needs_plugin(&changed));
+        assert!(subscription(json!({ "owner": "ada" })).needs_plugin(&changed));
+
+        // Deletions are told apart by the plugins that matched before.
+        let deleted = update(MutationKind::Delete, 1);
+        assert!(!subscription(json!({ "tag": "ai" })).needs_plugin(&deleted));
+    }
+
+    #[test]
+    fn lookup_free_filters_come_first() {
+        let changed = update(MutationKind::Update, 1);
+
+        assert!(!subscription(json!({ "tag": "ai", "plugin_id": 2 })).needs_plugin(&changed));
+        assert!(
+            !subscription(json!({ "owner": "ada", "mutation_kinds": ["create"] }))
+                .needs_plugin(&changed)
+        );
+        // Updates are followed even when only deletions are delivered, so a
+        // plugin that stopped matching isn't reported deleted.
+        assert!(
+            subscription(json!({ "tag": "ai", "mutation_kinds": ["delete"] }))
+                .needs_plugin(&changed)
+        );
+
+        let mut by_id = subscription(json!({ "plugin_id": 1, "mutation_kinds": ["delete"] }));
+        assert!(!by_id.wants(&changed, None));
+        assert!(by_id.wants(&update(MutationKind::Delete, 1), None));
+        assert!(!by_id.wants(&update(MutationKind::Delete, 2), None));
+    }
+}